@@ -0,0 +1,244 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::path::PathBuf;
+
+use chrono::{ Datelike, NaiveDate, NaiveDateTime, Weekday };
+use serde::{ Deserialize, Serialize };
+
+use crate::ClockTime;
+
+pub const WEEKDAYS: [Weekday; 7] = [
+	Weekday::Mon,
+	Weekday::Tue,
+	Weekday::Wed,
+	Weekday::Thu,
+	Weekday::Fri,
+	Weekday::Sat,
+	Weekday::Sun,
+];
+
+/// Days an alarm goes off on. Saved as its display text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Repeat {
+	Daily,
+	Weekdays,
+	Weekends,
+	/// Set of days, with Monday in the lowest bit.
+	Days(u8),
+	/// The next time the alarm time comes around, after which the alarm turns itself off.
+	Once,
+}
+
+impl Repeat {
+	pub fn day_bit(day: Weekday) -> u8 {
+		1 << day.num_days_from_monday()
+	}
+
+	pub fn includes(&self, day: Weekday) -> bool {
+		match self {
+			Repeat::Daily | Repeat::Once => true,
+			Repeat::Weekdays => !matches!(day, Weekday::Sat | Weekday::Sun),
+			Repeat::Weekends => matches!(day, Weekday::Sat | Weekday::Sun),
+			Repeat::Days(days) => days & Self::day_bit(day) != 0,
+		}
+	}
+}
+
+impl fmt::Display for Repeat {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Repeat::Daily => write!(f, "Daily"),
+			Repeat::Weekdays => write!(f, "Weekdays"),
+			Repeat::Weekends => write!(f, "Weekends"),
+			Repeat::Once => write!(f, "Once"),
+			Repeat::Days(0) => write!(f, "Never"),
+			Repeat::Days(_) => {
+				let days = WEEKDAYS.iter()
+					.filter(|&&day| self.includes(day))
+					.map(|day| day_name(*day))
+					.collect::<Vec<_>>();
+
+				write!(f, "{}", days.join(" "))
+			}
+		}
+	}
+}
+
+impl FromStr for Repeat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"Daily" => Ok(Repeat::Daily),
+			"Weekdays" => Ok(Repeat::Weekdays),
+			"Weekends" => Ok(Repeat::Weekends),
+			"Once" => Ok(Repeat::Once),
+			"Never" => Ok(Repeat::Days(0)),
+			_ => s.split(' ')
+				.try_fold(0, |days, name| WEEKDAYS.iter()
+					.find(|&&day| day_name(day) == name)
+					.map(|&day| days | Repeat::day_bit(day))
+					.ok_or_else(|| format!("Unknown repeat {:?}", s))
+				)
+				.map(Repeat::Days),
+		}
+	}
+}
+
+impl From<Repeat> for String {
+	fn from(repeat: Repeat) -> Self {
+		repeat.to_string()
+	}
+}
+
+impl TryFrom<String> for Repeat {
+	type Error = String;
+
+	fn try_from(s: String) -> Result<Self, Self::Error> {
+		s.parse()
+	}
+}
+
+pub fn day_name(day: Weekday) -> &'static str {
+	match day {
+		Weekday::Mon => "Mon",
+		Weekday::Tue => "Tue",
+		Weekday::Wed => "Wed",
+		Weekday::Thu => "Thu",
+		Weekday::Fri => "Fri",
+		Weekday::Sat => "Sat",
+		Weekday::Sun => "Sun",
+	}
+}
+
+/// Task needed to dismiss a ringing alarm, so it isn't turned off half asleep.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Challenge {
+	/// A single press dismisses the alarm.
+	None,
+	/// Press the buttons shown on the display, in order.
+	Sequence,
+	/// Hold mode for a few seconds.
+	Hold,
+}
+
+impl fmt::Display for Challenge {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Challenge::None => write!(f, "None"),
+			Challenge::Sequence => write!(f, "Sequence"),
+			Challenge::Hold => write!(f, "Hold"),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Alarm {
+	pub time: ClockTime,
+	pub song: Option<PathBuf>,
+	pub enabled: bool,
+	pub repeat: Repeat,
+	/// Challenge to dismiss the alarm, or `None` for the one in the config.
+	pub challenge: Option<Challenge>,
+	/// Minutes before the alarm to start playing its song gently, or 0 for no gentle wake.
+	#[serde(default)]
+	pub wake_minutes: u32,
+	/// When the alarm was last due and went off, so it doesn't go off twice if the clock is set back.
+	#[serde(skip)]
+	pub last_rang: Option<NaiveDateTime>,
+}
+
+impl Alarm {
+	pub fn new(time: ClockTime, song: Option<PathBuf>) -> Self {
+		Self { time, song, enabled: true, repeat: Repeat::Daily, challenge: None, wake_minutes: 0, last_rang: None }
+	}
+
+	pub fn challenge_name(&self) -> String {
+		self.challenge.map_or("Default".to_owned(), |challenge| challenge.to_string())
+	}
+
+	pub fn wake_name(&self) -> String {
+		match self.wake_minutes {
+			0 => "Off".to_owned(),
+			minutes => format!("{}m", minutes),
+		}
+	}
+
+	pub fn on_off(&self) -> &'static str {
+		if self.enabled { "On" } else { "Off" }
+	}
+
+	/// Whether the alarm should go off at its time on the given date.
+	pub fn rings_on(&self, date: NaiveDate) -> bool {
+		self.enabled && self.repeat.includes(date.weekday())
+	}
+
+	/// The latest time after `since` and up to `now` that the alarm is due to go off, unless it has
+	/// already gone off then.
+	pub fn due_between(&self, since: NaiveDateTime, now: NaiveDateTime) -> Option<NaiveDateTime> {
+		let mut date = now.date();
+
+		let due = loop {
+			let due = date.and_time(self.time.into());
+
+			if due <= since {
+				return None;
+			} else if due <= now && self.rings_on(date) {
+				break due;
+			}
+
+			date = date.pred();
+		};
+
+		match self.last_rang {
+			Some(last_rang) if last_rang >= due => None,
+			_ => Some(due),
+		}
+	}
+}
+
+impl fmt::Display for Alarm {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} {} {}", self.time.text(), self.repeat, self.on_off())
+	}
+}
+
+/// Countdown timer left running, which rings like an alarm when it ends. Also used for nap alarms.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Timer {
+	pub ends_at: NaiveDateTime,
+	pub song: PathBuf,
+}
+
+/// Sleep timer for music played in play mode, which stops the player when it ends.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SleepTimer {
+	pub ends_at: NaiveDateTime,
+	/// Speed last sent to the player while slowing down towards the end.
+	pub speed: f64,
+}
+
+/// An alarm which has gone off and not been dismissed yet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ringing {
+	pub song: PathBuf,
+	pub challenge: Challenge,
+	/// Number of times the alarm has been snoozed.
+	pub snoozes: u32,
+	/// When the alarm goes off again, if it is snoozed, read from `Clock::steady_now`.
+	pub snoozed_until: Option<NaiveDateTime>,
+	/// When the alarm went off, or last went off again after a snooze, read from
+	/// `Clock::steady_now`.
+	pub since: NaiveDateTime,
+	/// Number of escalation steps taken.
+	pub escalations: usize,
+}
+
+impl Ringing {
+	pub fn new(song: PathBuf, challenge: Challenge, since: NaiveDateTime) -> Self {
+		Self { song, challenge, snoozes: 0, snoozed_until: None, since, escalations: 0 }
+	}
+}
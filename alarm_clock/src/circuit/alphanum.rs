@@ -1,5 +1,7 @@
 use rppal::i2c::I2c;
 
+use crate::circuit::{ AlphanumDisplay, Font };
+
 #[allow(dead_code)] const HT16K33_BLINK_CMD: u8 = 0x80; //< I2C register for BLINK setting
 #[allow(dead_code)] const HT16K33_BLINK_DISPLAYON: u8 = 0x01; //< I2C value for steady on
 #[allow(dead_code)] const HT16K33_BLINK_OFF: u8 = 0; //< I2C value for steady off
@@ -9,12 +11,21 @@ use rppal::i2c::I2c;
 
 #[allow(dead_code)] const HT16K33_CMD_BRIGHTNESS: u8 = 0xE0; //< I2C register for BRIGHTNESS setting
 
+/// Segment of the decimal point after each character.
+pub const DECIMAL_POINT: u16 = 0b_0100_0000_0000_0000;
+
+/// Characters shown by each HT16K33 backpack.
+pub const BACKPACK_CHARS: usize = 4;
+
+/// One or more HT16K33 backpacks on the I2C bus, chained left to right into one display.
 pub struct Alphanum {
 	i2c: I2c,
+	addresses: Vec<u16>,
 	ascii_uppercase: bool,
+	font: Font,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[allow(dead_code)]
 pub enum BlinkRate {
 	Off,
@@ -23,17 +34,55 @@ pub enum BlinkRate {
 	TwoHz,
 }
 
+/// A character on the display, with the decimal point after it lit or not.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Glyph {
+	pub c: char,
+	pub dot: bool,
+}
+
+impl From<char> for Glyph {
+	fn from(c: char) -> Self {
+		Self { c, dot: false }
+	}
+}
+
+/// Glyphs showing text, where a '.' lights the decimal point of the character before it rather than
+/// taking a character of its own, so "12.34" fits in four.
+pub fn glyphs(text: &str) -> Vec<Glyph> {
+	let mut glyphs = Vec::<Glyph>::new();
+
+	for c in text.chars() {
+		match glyphs.last_mut() {
+			Some(last) if c == '.' && !last.dot => last.dot = true,
+			_ if c == '.' => glyphs.push(Glyph { c: ' ', dot: true }),
+			_ => glyphs.push(Glyph::from(c)),
+		}
+	}
+
+	glyphs
+}
+
+/// Text for glyphs, the other way round from [`glyphs`].
+#[cfg(test)]
+pub fn glyph_text(glyphs: &[Glyph]) -> String {
+	glyphs.iter()
+		.flat_map(|glyph| std::iter::once(glyph.c).chain(if glyph.dot { Some('.') } else { None }))
+		.collect()
+}
+
 impl Alphanum {
-	pub fn new() -> rppal::i2c::Result<Self> {
+	/// Set up the backpacks at `addresses` on I2C bus `bus`, in order from the left of the display.
+	pub fn new(bus: u8, addresses: &[u16]) -> rppal::i2c::Result<Self> {
 		let mut val = Self {
-			i2c: I2c::new()?,
+			i2c: I2c::with_bus(bus)?,
+			addresses: addresses.to_vec(),
 			ascii_uppercase: false,
+			font: Font::default(),
 		};
 
-		val.i2c.set_slave_address(0x70)?;
-
 		// turn on oscillator
-		val.i2c.write(&[0x21])?;
+		val.write_all(&[0x21])?;
 
 		val.set_brightness(15)?;
 		val.blink_rate(BlinkRate::Off)?;
@@ -41,11 +90,27 @@ impl Alphanum {
 		Ok(val)
 	}
 
-	pub fn set_brightness(&mut self, brightness: u8) -> rppal::i2c::Result<usize> {
-		self.i2c.write(&[HT16K33_CMD_BRIGHTNESS | std::cmp::min(brightness, 15)])
+	/// Write the same bytes to every backpack.
+	fn write_all(&mut self, bytes: &[u8]) -> rppal::i2c::Result<()> {
+		for &address in &self.addresses {
+			self.i2c.set_slave_address(address)?;
+			self.i2c.write(bytes)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl AlphanumDisplay for Alphanum {
+	type Error = rppal::i2c::Error;
+
+	fn set_brightness(&mut self, brightness: u8) -> rppal::i2c::Result<()> {
+		self.write_all(&[HT16K33_CMD_BRIGHTNESS | std::cmp::min(brightness, 15)])?;
+
+		Ok(())
 	}
 
-	pub fn blink_rate(&mut self, blink_rate: BlinkRate) -> rppal::i2c::Result<usize> {
+	fn blink_rate(&mut self, blink_rate: BlinkRate) -> rppal::i2c::Result<()> {
 		let blink_rate = match blink_rate {
 			BlinkRate::Off => HT16K33_BLINK_OFF,
 			BlinkRate::TwoHz => HT16K33_BLINK_2HZ,
@@ -53,34 +118,47 @@ impl Alphanum {
 			BlinkRate::HalfHz => HT16K33_BLINK_HALFHZ,
 		};
 
-		self.i2c.write(&[HT16K33_BLINK_CMD | HT16K33_BLINK_DISPLAYON | (blink_rate << 1)])
+		self.write_all(&[HT16K33_BLINK_CMD | HT16K33_BLINK_DISPLAYON | (blink_rate << 1)])?;
+
+		Ok(())
 	}
 
-	pub fn ascii_uppercase(&mut self, ascii_uppercase: bool) {
+	fn ascii_uppercase(&mut self, ascii_uppercase: bool) {
 		self.ascii_uppercase = ascii_uppercase;
 	}
 
-	pub fn display(&mut self, chars: &[char; 4]) -> rppal::i2c::Result<usize> {
-		let mut bytes = [0_u8; 9];
+	fn font(&mut self, font: Font) {
+		self.font = font;
+	}
+
+	fn width(&self) -> usize {
+		self.addresses.len() * BACKPACK_CHARS
+	}
 
-		for i in 0..4 {
-			let mut c = chars[i];
+	fn display_glyphs(&mut self, glyphs: &[Glyph]) -> rppal::i2c::Result<()> {
+		let blank = Glyph::from(' ');
+		let mut glyphs = glyphs.iter().chain(std::iter::repeat(&blank));
 
-			if self.ascii_uppercase {
-				c = c.to_ascii_uppercase()
+		for i in 0..self.addresses.len() {
+			let mut bytes = [0_u8; 1 + BACKPACK_CHARS * 2];
+
+			for (j, &glyph) in glyphs.by_ref().take(BACKPACK_CHARS).enumerate() {
+				let char_bytes = self.font.render(glyph, self.ascii_uppercase).to_le_bytes();
+				bytes[j*2+1] = char_bytes[0];
+				bytes[j*2+2] = char_bytes[1];
 			}
 
-			let char_bytes = char_to_alphanum(c).to_le_bytes();
-			bytes[i*2+1] = char_bytes[0];
-			bytes[i*2+2] = char_bytes[1];
+			self.i2c.set_slave_address(self.addresses[i])?;
+			self.i2c.write(&bytes)?;
 		}
 
-		self.i2c.write(&bytes)
+		Ok(())
 	}
 }
 
-fn char_to_alphanum(c: char) -> u16 {
-	match c {
+/// Segments of the built-in table for a character, if it has one.
+pub fn char_to_alphanum(c: char) -> Option<u16> {
+	let segments = match c {
 		' ' => 0b_0000_0000_0000_0000,
 		'!' => 0b_0000_0000_0000_0110,
 		'"' => 0b_0000_0010_0010_0000,
@@ -176,6 +254,8 @@ fn char_to_alphanum(c: char) -> u16 {
 		'|' => 0b_0001_0010_0000_0000,
 		'}' => 0b_0010_0100_1000_1001,
 		'~' => 0b_0000_0101_0010_0000,
-		_ => 0b_0011_1111_1111_1111,
-	}
+		_ => return None,
+	};
+
+	Some(segments)
 }
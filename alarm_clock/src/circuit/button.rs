@@ -1,4 +1,9 @@
-use rppal::gpio::{ Gpio, InputPin, Trigger };
+use std::time::Duration;
+
+use rppal::gpio::{ Gpio, InputPin, Level, Trigger };
+
+use crate::circuit::InputSource;
+use crate::message::ButtonEvent;
 
 pub struct Button {
 	pin: InputPin,
@@ -10,10 +15,46 @@ impl Button {
 
 		pin.set_interrupt(Trigger::Both)?;
 
-		Ok(Button { pin: pin })
+		Ok(Button { pin })
 	}
 
 	pub fn input_pin(&self) -> &InputPin {
 		&self.pin
 	}
 }
+
+/// Buttons on gpio pins, numbered in the order of the pins given.
+pub struct Buttons {
+	gpio: Gpio,
+	buttons: Vec<Button>,
+}
+
+impl Buttons {
+	pub fn new(pin_nums: &[u8]) -> rppal::gpio::Result<Self> {
+		Ok(Self {
+			gpio: Gpio::new()?,
+			buttons: pin_nums.iter()
+				.map(|&p| Button::new(p))
+				.collect::<rppal::gpio::Result<Vec<_>>>()?,
+		})
+	}
+}
+
+impl InputSource for Buttons {
+	type Error = rppal::gpio::Error;
+
+	fn poll(&mut self, timeout: Option<Duration>) -> rppal::gpio::Result<Option<ButtonEvent>> {
+		let pins: Vec<_> = self.buttons.iter()
+			.map(|button| button.input_pin())
+			.collect();
+
+		Ok(self.gpio.poll_interrupts(&pins, false, timeout)?.and_then(|(pin, level)| {
+			let i = self.buttons.iter().position(|b| b.input_pin().pin() == pin.pin())? as u8;
+
+			Some(match level {
+				Level::High => ButtonEvent::Release(i),
+				Level::Low => ButtonEvent::Press(i),
+			})
+		}))
+	}
+}
@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use std::time;
 
 use rppal::gpio::{ Gpio, OutputPin };
+use crate::circuit::ToneOutput;
 use crate::note::Note;
 
 pub struct Buzzer<N> where
@@ -19,24 +20,28 @@ impl<N> Buzzer<N> where
 		let pin = Gpio::new()?.get(pin_num)?.into_output();
 
 		Ok(Buzzer {
-			pin: pin,
+			pin,
 			notes: HashMap::default(),
 		})
 	}
+}
 
-	pub fn add_note(&mut self, note: N) {
+impl<N> ToneOutput<N> for Buzzer<N> where
+	N: Note + Hash + Eq
+{
+	fn add_note(&mut self, note: N) {
 		self.notes.insert(note, time::Instant::now());
 	}
 
-	pub fn remove_note(&mut self, note: &N) {
+	fn remove_note(&mut self, note: &N) {
 		self.notes.remove(note);
 	}
 
-	pub fn clear(&mut self) {
+	fn clear(&mut self) {
 		self.notes.clear();
 	}
 
-	pub fn update(&mut self) {
+	fn update(&mut self) {
 		let now = time::Instant::now();
 
 		let mut play = false;
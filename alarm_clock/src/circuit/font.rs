@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::circuit::alphanum::{ char_to_alphanum, Glyph, DECIMAL_POINT };
+
+/// Segments lit for characters the font doesn't know, even after substitution.
+pub const UNKNOWN_SEGMENTS: u16 = 0b_0011_1111_1111_1111;
+
+/// Segments lit for each character, on top of the built-in table.
+///
+/// Loaded from a TOML file like:
+///
+/// ```toml
+/// [glyphs]
+/// "°" = 0b0000_0000_1110_0011
+///
+/// [substitutions]
+/// "é" = "e"
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct Font {
+	/// Segments of characters, as 16 bit masks laid out as in the built-in table. These replace the
+	/// built-in segments of the same characters.
+	#[serde(default)]
+	glyphs: HashMap<char, u16>,
+	/// Characters shown as another character when neither the font nor the built-in table has them.
+	#[serde(default)]
+	substitutions: HashMap<char, char>,
+}
+
+impl Font {
+	/// The character to show for `c`, which is its substitution if there are no segments for it.
+	pub fn substitute(&self, c: char) -> char {
+		match self.substitutions.get(&c) {
+			Some(&substitute) if self.known_segments(c).is_none() => substitute,
+			_ => c,
+		}
+	}
+
+	/// The glyph shown for `glyph`, after substitution and, with `ascii_uppercase`, in uppercase.
+	pub fn shown(&self, glyph: Glyph, ascii_uppercase: bool) -> Glyph {
+		let c = self.substitute(glyph.c);
+
+		if ascii_uppercase {
+			Glyph { c: c.to_ascii_uppercase(), ..glyph }
+		} else {
+			Glyph { c, ..glyph }
+		}
+	}
+
+	/// Segments lit to show `glyph` on the display.
+	pub fn render(&self, glyph: Glyph, ascii_uppercase: bool) -> u16 {
+		self.segments(self.shown(glyph, ascii_uppercase))
+	}
+
+	pub fn segments(&self, glyph: Glyph) -> u16 {
+		let segments = self.known_segments(glyph.c).unwrap_or(UNKNOWN_SEGMENTS);
+
+		if glyph.dot {
+			segments | DECIMAL_POINT
+		} else {
+			segments
+		}
+	}
+
+	fn known_segments(&self, c: char) -> Option<u16> {
+		self.glyphs.get(&c).copied().or_else(|| char_to_alphanum(c))
+	}
+}
@@ -0,0 +1,188 @@
+//! In-memory backends for running without the Raspberry Pi hardware.
+//!
+//! Each mock reports what would have happened on the hardware through a channel, so tests can
+//! inspect it while the mock itself is owned by one of the threads.
+
+use std::convert::Infallible;
+use std::sync::{ mpsc, Arc };
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use crate::circuit::{ AlphanumDisplay, BlinkRate, Font, Glyph, I2cDevice, InputSource, ToneOutput };
+use crate::message::ButtonEvent;
+use crate::note::Note;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ToneEvent<N> {
+	On(N),
+	Off(N),
+	Clear,
+}
+
+pub struct MockBuzzer<N> {
+	sender: mpsc::Sender<ToneEvent<N>>,
+}
+
+impl<N> MockBuzzer<N> where
+	N: Note + Clone
+{
+	pub fn new() -> (Self, mpsc::Receiver<ToneEvent<N>>) {
+		let (sender, receiver) = mpsc::channel();
+
+		(Self { sender }, receiver)
+	}
+}
+
+impl<N> ToneOutput<N> for MockBuzzer<N> where
+	N: Note + Clone
+{
+	fn add_note(&mut self, note: N) {
+		let _ = self.sender.send(ToneEvent::On(note));
+	}
+
+	fn remove_note(&mut self, note: &N) {
+		let _ = self.sender.send(ToneEvent::Off(note.clone()));
+	}
+
+	fn clear(&mut self) {
+		let _ = self.sender.send(ToneEvent::Clear);
+	}
+
+	fn update(&mut self) {
+		// nothing to drive, but avoid spinning a core in a tight loop
+		std::thread::yield_now();
+	}
+}
+
+/// Input fed from a channel. Polling fails once every sender is dropped.
+pub struct MockInput {
+	receiver: mpsc::Receiver<ButtonEvent>,
+}
+
+impl MockInput {
+	pub fn new() -> (Self, mpsc::Sender<ButtonEvent>) {
+		let (sender, receiver) = mpsc::channel();
+
+		(Self { receiver }, sender)
+	}
+}
+
+impl InputSource for MockInput {
+	type Error = mpsc::RecvError;
+
+	fn poll(&mut self, timeout: Option<Duration>) -> Result<Option<ButtonEvent>, mpsc::RecvError> {
+		match timeout {
+			Some(timeout) => match self.receiver.recv_timeout(timeout) {
+				Ok(event) => Ok(Some(event)),
+				Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+				Err(mpsc::RecvTimeoutError::Disconnected) => Err(mpsc::RecvError),
+			}
+			None => self.receiver.recv().map(Some),
+		}
+	}
+}
+
+pub struct MockAlphanum {
+	sender: mpsc::Sender<Vec<Glyph>>,
+	width: usize,
+	ascii_uppercase: bool,
+	font: Font,
+}
+
+impl MockAlphanum {
+	/// Create a four character display which sends every frame it shows to the returned receiver.
+	pub fn new() -> (Self, mpsc::Receiver<Vec<Glyph>>) {
+		Self::with_width(4)
+	}
+
+	/// Create a display `width` characters wide, like several chained together.
+	pub fn with_width(width: usize) -> (Self, mpsc::Receiver<Vec<Glyph>>) {
+		let (sender, receiver) = mpsc::channel();
+
+		(Self { sender, width, ascii_uppercase: false, font: Font::default() }, receiver)
+	}
+}
+
+impl AlphanumDisplay for MockAlphanum {
+	type Error = Infallible;
+
+	fn set_brightness(&mut self, _brightness: u8) -> Result<(), Infallible> {
+		Ok(())
+	}
+
+	fn blink_rate(&mut self, _blink_rate: BlinkRate) -> Result<(), Infallible> {
+		Ok(())
+	}
+
+	fn ascii_uppercase(&mut self, ascii_uppercase: bool) {
+		self.ascii_uppercase = ascii_uppercase;
+	}
+
+	fn font(&mut self, font: Font) {
+		self.font = font;
+	}
+
+	fn width(&self) -> usize {
+		self.width
+	}
+
+	fn display_glyphs(&mut self, glyphs: &[Glyph]) -> Result<(), Infallible> {
+		let mut glyphs = glyphs.to_vec();
+		glyphs.resize(self.width, Glyph::from(' '));
+
+		for glyph in glyphs.iter_mut() {
+			*glyph = self.font.shown(*glyph, self.ascii_uppercase);
+		}
+
+		let _ = self.sender.send(glyphs);
+
+		Ok(())
+	}
+}
+
+/// I2C device made of registers, where writes and reads start at the register address written first
+/// and move on to the next register after each byte.
+pub struct MockI2c {
+	registers: Arc<Mutex<Vec<u8>>>,
+}
+
+impl MockI2c {
+	/// Create a device with `len` registers, which can be inspected and changed through the returned
+	/// handle.
+	pub fn new(len: usize) -> (Self, Arc<Mutex<Vec<u8>>>) {
+		let registers = Arc::new(Mutex::new(vec![0; len]));
+
+		(Self { registers: registers.clone() }, registers)
+	}
+}
+
+impl I2cDevice for MockI2c {
+	type Error = Infallible;
+
+	fn write(&mut self, bytes: &[u8]) -> Result<(), Infallible> {
+		if let [address, values @ ..] = bytes {
+			let mut registers = self.registers.lock();
+			let len = registers.len();
+
+			for (i, &value) in values.iter().enumerate() {
+				registers[(*address as usize + i) % len] = value;
+			}
+		}
+
+		Ok(())
+	}
+
+	fn write_read(&mut self, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Infallible> {
+		self.write(bytes)?;
+
+		let registers = self.registers.lock();
+		let address = bytes.first().copied().unwrap_or(0) as usize;
+
+		for (i, value) in buffer.iter_mut().enumerate() {
+			*value = registers[(address + i) % registers.len()];
+		}
+
+		Ok(())
+	}
+}
@@ -1,7 +1,76 @@
+use std::time::Duration;
+
+use crate::message::ButtonEvent;
+use crate::note::Note;
+
 mod buzzer;
 mod button;
 mod alphanum;
+mod font;
+mod rtc;
+#[cfg(test)] pub mod mock;
+pub mod terminal;
 
 pub use buzzer::Buzzer;
-pub use button::Button;
-pub use alphanum::{ Alphanum, BlinkRate };
+pub use button::Buttons;
+pub use alphanum::{ glyphs, Alphanum, BlinkRate, Glyph, BACKPACK_CHARS };
+#[cfg(test)] pub use alphanum::glyph_text;
+pub use font::Font;
+pub use rtc::Ds3231;
+
+/// Output capable of sounding several notes at once, such as the piezo buzzer.
+pub trait ToneOutput<N: Note> {
+	fn add_note(&mut self, note: N);
+
+	fn remove_note(&mut self, note: &N);
+
+	fn clear(&mut self);
+
+	/// Called continuously to drive the output for all currently sounding notes.
+	fn update(&mut self);
+}
+
+/// Source of button presses and releases.
+pub trait InputSource {
+	type Error;
+
+	/// Wait for the next input change, for at most `timeout` if given. `None` means nothing changed
+	/// before giving up.
+	fn poll(&mut self, timeout: Option<Duration>) -> Result<Option<ButtonEvent>, Self::Error>;
+}
+
+/// Display showing a row of characters at a time.
+pub trait AlphanumDisplay {
+	type Error;
+
+	fn set_brightness(&mut self, brightness: u8) -> Result<(), Self::Error>;
+
+	fn blink_rate(&mut self, blink_rate: BlinkRate) -> Result<(), Self::Error>;
+
+	fn ascii_uppercase(&mut self, ascii_uppercase: bool);
+
+	/// Font to look up the segments of characters in.
+	fn font(&mut self, font: Font);
+
+	/// Number of characters shown at once.
+	fn width(&self) -> usize;
+
+	/// Show characters from the left, with any of their decimal points lit. Characters past the width
+	/// are left out, and the rest of the display is blank.
+	fn display_glyphs(&mut self, glyphs: &[Glyph]) -> Result<(), Self::Error>;
+
+	fn display(&mut self, chars: &[char]) -> Result<(), Self::Error> {
+		self.display_glyphs(&chars.iter().copied().map(Glyph::from).collect::<Vec<_>>())
+	}
+}
+
+/// Device on the I2C bus, with registers read and written from a register address.
+pub trait I2cDevice {
+	type Error;
+
+	/// Write bytes, the first of which is usually a register address.
+	fn write(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+
+	/// Write bytes, then read into `buffer`.
+	fn write_read(&mut self, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error>;
+}
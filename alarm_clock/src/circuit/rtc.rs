@@ -0,0 +1,98 @@
+use chrono::{ Datelike, NaiveDate, NaiveDateTime, Timelike };
+use rppal::i2c::I2c;
+
+use crate::circuit::I2cDevice;
+
+const DS3231_ADDRESS: u16 = 0x68;
+
+const DS3231_TIME: u8 = 0x00; //< first of the seconds, minutes, hours, weekday, date, month, year registers
+const DS3231_STATUS: u8 = 0x0F;
+const DS3231_OSF: u8 = 0x80; //< status bit set when the oscillator stopped, so the time is lost
+
+const DS3231_12_HOUR: u8 = 0x40; //< hours register bit for 12 hour mode
+const DS3231_PM: u8 = 0x20; //< hours register bit for PM in 12 hour mode
+const DS3231_CENTURY: u8 = 0x80; //< month register bit for the year after 2099
+
+/// Battery backed real time clock, which keeps time while the Pi is off.
+pub struct Ds3231<D> {
+	device: D,
+}
+
+impl Ds3231<I2c> {
+	/// Connect to the clock on I2C bus `bus`, which is usually the display's.
+	pub fn on_i2c(bus: u8) -> rppal::i2c::Result<Self> {
+		let mut i2c = I2c::with_bus(bus)?;
+		i2c.set_slave_address(DS3231_ADDRESS)?;
+
+		Ok(Self::new(i2c))
+	}
+}
+
+impl<D: I2cDevice> Ds3231<D> {
+	pub fn new(device: D) -> Self {
+		Self { device }
+	}
+
+	/// The time kept by the clock, or `None` if it lost track of the time since it was last set.
+	pub fn read_time(&mut self) -> Result<Option<NaiveDateTime>, D::Error> {
+		let mut status = [0];
+		self.device.write_read(&[DS3231_STATUS], &mut status)?;
+
+		if status[0] & DS3231_OSF != 0 {
+			return Ok(None);
+		}
+
+		let mut regs = [0; 7];
+		self.device.write_read(&[DS3231_TIME], &mut regs)?;
+
+		let hours = if regs[2] & DS3231_12_HOUR != 0 {
+			from_bcd(regs[2] & 0x1F) % 12 + if regs[2] & DS3231_PM != 0 { 12 } else { 0 }
+		} else {
+			from_bcd(regs[2] & 0x3F)
+		};
+		let century = if regs[5] & DS3231_CENTURY != 0 { 2100 } else { 2000 };
+
+		Ok(NaiveDate::from_ymd_opt(century + from_bcd(regs[6]) as i32, from_bcd(regs[5] & 0x1F), from_bcd(regs[4]))
+			.and_then(|date| date.and_hms_opt(hours, from_bcd(regs[1]), from_bcd(regs[0] & 0x7F))))
+	}
+
+	/// Set the clock, in 24 hour mode, and mark the time as kept from now on.
+	pub fn write_time(&mut self, now: NaiveDateTime) -> Result<(), D::Error> {
+		let century = if now.year() >= 2100 { DS3231_CENTURY } else { 0 };
+
+		self.device.write(&[
+			DS3231_TIME,
+			to_bcd(now.second()),
+			to_bcd(now.minute()),
+			to_bcd(now.hour()),
+			to_bcd(now.weekday().number_from_monday()),
+			to_bcd(now.day()),
+			to_bcd(now.month()) | century,
+			to_bcd(now.year().rem_euclid(100) as u32),
+		])?;
+
+		let mut status = [0];
+		self.device.write_read(&[DS3231_STATUS], &mut status)?;
+		self.device.write(&[DS3231_STATUS, status[0] & !DS3231_OSF])
+	}
+}
+
+impl I2cDevice for I2c {
+	type Error = rppal::i2c::Error;
+
+	fn write(&mut self, bytes: &[u8]) -> rppal::i2c::Result<()> {
+		I2c::write(self, bytes).map(|_| ())
+	}
+
+	fn write_read(&mut self, bytes: &[u8], buffer: &mut [u8]) -> rppal::i2c::Result<()> {
+		I2c::write_read(self, bytes, buffer)
+	}
+}
+
+fn from_bcd(bcd: u8) -> u32 {
+	(bcd >> 4) as u32 * 10 + (bcd & 0x0F) as u32
+}
+
+fn to_bcd(value: u32) -> u8 {
+	(((value / 10) << 4) | (value % 10)) as u8
+}
@@ -0,0 +1,300 @@
+use std::collections::BTreeSet;
+use std::io::{ self, Read, Write };
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::circuit::{ AlphanumDisplay, BlinkRate, Font, Glyph, InputSource, ToneOutput };
+use crate::message::ButtonEvent;
+use crate::note::MidiNote;
+
+/// Row of the terminal where the display is drawn, with status lines below it.
+const DISPLAY_ROW: u16 = 1;
+const STATUS_ROW: u16 = DISPLAY_ROW + 6;
+const NOTES_ROW: u16 = STATUS_ROW + 1;
+const HELP_ROW: u16 = NOTES_ROW + 1;
+/// First row used for scrolling log output.
+const LOG_ROW: u16 = HELP_ROW + 2;
+
+const HELP: &str = "down: j / \u{2193}   up: k / \u{2191}   mode: m / enter   hold mode: M   quit: q";
+
+static ORIGINAL_TERMIOS: Lazy<Mutex<Option<libc::termios>>> = Lazy::new(|| Mutex::new(None));
+
+/// Prepare the terminal for the simulator.
+///
+/// Keys are read one at a time without echo, and everything printed to stdout scrolls below the
+/// drawn display instead of over it. Call [`restore`] before exiting.
+pub fn init() -> io::Result<()> {
+	unsafe {
+		let mut termios = std::mem::zeroed::<libc::termios>();
+
+		if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+			return Err(io::Error::last_os_error());
+		}
+
+		*ORIGINAL_TERMIOS.lock() = Some(termios);
+
+		// ctrl-c is read as a key so the terminal can be restored before exiting
+		termios.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+		termios.c_cc[libc::VMIN] = 1;
+		termios.c_cc[libc::VTIME] = 0;
+
+		if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+			return Err(io::Error::last_os_error());
+		}
+	}
+
+	let mut stdout = io::stdout().lock();
+
+	// clear screen, keep logs below the display, and move into the log area
+	write!(stdout, "\x1b[2J\x1b[{};{}r\x1b[{};1H", LOG_ROW, rows(), LOG_ROW)?;
+	stdout.flush()?;
+	drop(stdout);
+
+	draw(HELP_ROW, &[HELP.to_owned()])
+}
+
+/// Undo the changes made to the terminal by [`init`].
+pub fn restore() {
+	if let Some(termios) = ORIGINAL_TERMIOS.lock().take() {
+		unsafe {
+			libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+		}
+	}
+
+	let mut stdout = io::stdout().lock();
+	let _ = writeln!(stdout, "\x1b[r\x1b[{};1H", rows());
+	let _ = stdout.flush();
+}
+
+fn rows() -> u16 {
+	unsafe {
+		let mut size = std::mem::zeroed::<libc::winsize>();
+
+		if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_row > 0 {
+			size.ws_row
+		} else {
+			24
+		}
+	}
+}
+
+/// Draw lines starting at the given row, leaving the cursor where it was.
+fn draw(row: u16, lines: &[String]) -> io::Result<()> {
+	let mut stdout = io::stdout().lock();
+
+	write!(stdout, "\x1b7")?;
+
+	for (i, line) in lines.iter().enumerate() {
+		write!(stdout, "\x1b[{};1H\x1b[2K{}", row + i as u16, line)?;
+	}
+
+	write!(stdout, "\x1b8")?;
+	stdout.flush()
+}
+
+/// Render the segments of one 14-segment digit as five lines of text.
+///
+/// ```text
+///  ---
+/// |\|/|
+///  - -
+/// |/|\|
+///  --- .
+/// ```
+pub fn segment_lines(segments: u16) -> [String; 5] {
+	let on = |bit: u16, c: char| if segments & (1 << bit) != 0 { c } else { ' ' };
+
+	[
+		[' ', on(0, '-'), on(0, '-'), on(0, '-'), ' ', ' '],
+		[on(5, '|'), on(8, '\\'), on(9, '|'), on(10, '/'), on(1, '|'), ' '],
+		[' ', on(6, '-'), ' ', on(7, '-'), ' ', ' '],
+		[on(4, '|'), on(11, '/'), on(12, '|'), on(13, '\\'), on(2, '|'), ' '],
+		[' ', on(3, '-'), on(3, '-'), on(3, '-'), ' ', on(14, '.')],
+	].map(|row| row.iter().collect())
+}
+
+/// Display drawn as 14-segment digits at the top of the terminal.
+pub struct TerminalAlphanum {
+	width: usize,
+	ascii_uppercase: bool,
+	font: Font,
+	brightness: u8,
+	blink_rate: BlinkRate,
+}
+
+impl TerminalAlphanum {
+	pub fn new(width: usize) -> Self {
+		Self {
+			width,
+			ascii_uppercase: false,
+			font: Font::default(),
+			brightness: 15,
+			blink_rate: BlinkRate::Off,
+		}
+	}
+
+	fn draw_status(&self) -> io::Result<()> {
+		draw(STATUS_ROW, &[format!("brightness: {:2}   blink: {:?}", self.brightness, self.blink_rate)])
+	}
+}
+
+impl AlphanumDisplay for TerminalAlphanum {
+	type Error = io::Error;
+
+	fn set_brightness(&mut self, brightness: u8) -> io::Result<()> {
+		self.brightness = std::cmp::min(brightness, 15);
+		self.draw_status()
+	}
+
+	fn blink_rate(&mut self, blink_rate: BlinkRate) -> io::Result<()> {
+		self.blink_rate = blink_rate;
+		self.draw_status()
+	}
+
+	fn ascii_uppercase(&mut self, ascii_uppercase: bool) {
+		self.ascii_uppercase = ascii_uppercase;
+	}
+
+	fn font(&mut self, font: Font) {
+		self.font = font;
+	}
+
+	fn width(&self) -> usize {
+		self.width
+	}
+
+	fn display_glyphs(&mut self, glyphs: &[Glyph]) -> io::Result<()> {
+		let mut lines: [String; 5] = Default::default();
+		let blank = Glyph::from(' ');
+
+		for &glyph in glyphs.iter().chain(std::iter::repeat(&blank)).take(self.width) {
+			let segments = self.font.render(glyph, self.ascii_uppercase);
+
+			for (line, segment_line) in lines.iter_mut().zip(segment_lines(segments).iter()) {
+				line.push_str(segment_line);
+			}
+		}
+
+		draw(DISPLAY_ROW, &lines)
+	}
+}
+
+/// Buzzer which shows the names of the notes currently sounding.
+pub struct TerminalBuzzer {
+	notes: BTreeSet<MidiNote>,
+	changed: bool,
+}
+
+impl TerminalBuzzer {
+	pub fn new() -> Self {
+		Self { notes: BTreeSet::new(), changed: true }
+	}
+}
+
+impl ToneOutput<MidiNote> for TerminalBuzzer {
+	fn add_note(&mut self, note: MidiNote) {
+		self.changed |= self.notes.insert(note);
+	}
+
+	fn remove_note(&mut self, note: &MidiNote) {
+		self.changed |= self.notes.remove(note);
+	}
+
+	fn clear(&mut self) {
+		self.changed |= !self.notes.is_empty();
+		self.notes.clear();
+	}
+
+	fn update(&mut self) {
+		if self.changed {
+			let names = self.notes.iter().map(|n| n.name()).collect::<Vec<_>>();
+
+			let _ = draw(NOTES_ROW, &[format!("notes: {}", names.join(" "))]);
+
+			self.changed = false;
+		}
+
+		std::thread::sleep(Duration::from_millis(1));
+	}
+}
+
+/// Keyboard keys standing in for the buttons.
+///
+/// Terminals only report key presses, so each key taps its button, pressing and then releasing it.
+/// Mode can also be held down by one key press and let go by the next.
+pub struct TerminalInput {
+	stdin: io::Stdin,
+	/// Release to send after a tap.
+	release: Option<ButtonEvent>,
+	mode_held: bool,
+}
+
+impl TerminalInput {
+	pub fn new() -> Self {
+		Self { stdin: io::stdin(), release: None, mode_held: false }
+	}
+
+	/// Whether a key can be read within `timeout`, or ever if there is none.
+	fn wait(&self, timeout: Option<Duration>) -> io::Result<bool> {
+		let mut fd = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+		let timeout = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+
+		match unsafe { libc::poll(&mut fd, 1, timeout) } {
+			-1 => Err(io::Error::last_os_error()),
+			ready => Ok(ready > 0),
+		}
+	}
+
+	fn tap(&mut self, button: u8) -> Option<ButtonEvent> {
+		self.release = Some(ButtonEvent::Release(button));
+		Some(ButtonEvent::Press(button))
+	}
+
+	fn read_byte(&mut self) -> io::Result<u8> {
+		let mut byte = [0];
+		self.stdin.read_exact(&mut byte)?;
+
+		Ok(byte[0])
+	}
+}
+
+impl InputSource for TerminalInput {
+	type Error = io::Error;
+
+	fn poll(&mut self, timeout: Option<Duration>) -> io::Result<Option<ButtonEvent>> {
+		if let Some(release) = self.release.take() {
+			return Ok(Some(release));
+		}
+
+		if !self.wait(timeout)? {
+			return Ok(None);
+		}
+
+		Ok(match self.read_byte()? {
+			b'j' => self.tap(0),
+			b'k' => self.tap(1),
+			b'm' | b'\n' | b'\r' => self.tap(2),
+			b'M' => {
+				self.mode_held = !self.mode_held;
+
+				Some(match self.mode_held {
+					true => ButtonEvent::Press(2),
+					false => ButtonEvent::Release(2),
+				})
+			}
+			// arrow keys are sent as escape sequences
+			0x1b => match (self.read_byte()?, self.read_byte()?) {
+				(b'[', b'B') => self.tap(0),
+				(b'[', b'A') => self.tap(1),
+				_ => None,
+			}
+			b'q' | 0x03 => {
+				restore();
+				std::process::exit(0);
+			}
+			_ => None,
+		})
+	}
+}
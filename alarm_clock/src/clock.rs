@@ -0,0 +1,224 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::{ DateTime, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone, Utc };
+use chrono::offset::Local;
+use chrono_tz::Tz;
+
+use parking_lot::{ Mutex, RwLock };
+
+use crate::ClockTime;
+use crate::circuit::{ Ds3231, I2cDevice };
+
+/// Source of the date and time shown and used by the clock.
+pub trait Clock: Send + Sync {
+	fn now(&self) -> NaiveDateTime;
+
+	fn set(&self, now: NaiveDateTime);
+
+	fn date(&self) -> NaiveDate {
+		self.now().date()
+	}
+
+	fn clock_time(&self) -> ClockTime {
+		ClockTime::from(self.now().time())
+	}
+
+	/// Set the time of day, keeping the current date.
+	fn set_time(&self, time: ClockTime) {
+		self.set(self.date().and_time(NaiveTime::from(time)));
+	}
+
+	/// How far the clock has been set ahead of the system clock.
+	fn offset(&self) -> chrono::Duration {
+		self.now() - Local::now().naive_local()
+	}
+
+	/// Local time jumped over between two readings of the clock without any real time passing, like
+	/// the hour skipped when daylight saving time starts.
+	fn skipped(&self, _before: NaiveDateTime, _now: NaiveDateTime) -> chrono::Duration {
+		chrono::Duration::zero()
+	}
+
+	/// Reading to time countdowns against, which unlike `now` doesn't jump with daylight saving time.
+	/// It can only be compared with other readings of it, or turned back into local time with
+	/// `steady_to_local`.
+	fn steady_now(&self) -> NaiveDateTime {
+		self.now()
+	}
+
+	/// Local time of a reading of `steady_now`.
+	fn steady_to_local(&self, steady: NaiveDateTime) -> NaiveDateTime {
+		steady
+	}
+}
+
+/// Clock following real time, starting from the system's local time.
+pub struct RealClock {
+	zero: RwLock<(Instant, NaiveDateTime)>,
+}
+
+impl RealClock {
+	/// Start the clock `offset` ahead of the system's local time.
+	pub fn new(offset: chrono::Duration) -> Self {
+		Self { zero: RwLock::new((Instant::now(), Local::now().naive_local() + offset)) }
+	}
+}
+
+impl Clock for RealClock {
+	fn now(&self) -> NaiveDateTime {
+		let (instant, now) = *self.zero.read();
+
+		now + chrono::Duration::from_std(instant.elapsed()).unwrap_or_else(|_| chrono::Duration::zero())
+	}
+
+	fn set(&self, now: NaiveDateTime) {
+		*self.zero.write() = (Instant::now(), now);
+	}
+}
+
+/// Clock following the system's wall clock in a time zone, so it keeps in step with the system and
+/// changes with daylight saving time.
+///
+/// Setting it to a time in an hour skipped by daylight saving time sets it that far past the start
+/// of the skipped hour, and a time in a repeated hour sets it to the first time round.
+pub struct ZonedClock {
+	time_zone: Tz,
+	offset: RwLock<chrono::Duration>,
+}
+
+impl ZonedClock {
+	/// Start the clock `offset` ahead of the system clock.
+	pub fn new(time_zone: Tz, offset: chrono::Duration) -> Self {
+		Self { time_zone, offset: RwLock::new(offset) }
+	}
+
+	/// The moment a local time happens.
+	fn to_utc(&self, local: NaiveDateTime) -> DateTime<Utc> {
+		match self.time_zone.from_local_datetime(&local) {
+			LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => time.with_timezone(&Utc),
+			LocalResult::None => {
+				// use the offset from before the skipped hour, which lands as far past its end
+				let before = self.time_zone.from_utc_datetime(&(local - chrono::Duration::days(1)));
+				Utc.from_utc_datetime(&(local - before.offset().fix()))
+			}
+		}
+	}
+}
+
+impl Clock for ZonedClock {
+	fn now(&self) -> NaiveDateTime {
+		(Utc::now() + *self.offset.read()).with_timezone(&self.time_zone).naive_local()
+	}
+
+	fn set(&self, now: NaiveDateTime) {
+		*self.offset.write() = self.to_utc(now) - Utc::now();
+	}
+
+	fn offset(&self) -> chrono::Duration {
+		*self.offset.read()
+	}
+
+	fn skipped(&self, before: NaiveDateTime, now: NaiveDateTime) -> chrono::Duration {
+		let real = self.to_utc(now) - self.to_utc(before);
+
+		(now - before - real).max(chrono::Duration::zero())
+	}
+
+	fn steady_now(&self) -> NaiveDateTime {
+		(Utc::now() + *self.offset.read()).naive_utc()
+	}
+
+	fn steady_to_local(&self, steady: NaiveDateTime) -> NaiveDateTime {
+		self.time_zone.from_utc_datetime(&steady).naive_local()
+	}
+}
+
+/// Clock kept in a battery backed real time clock, so it has the time after a restart without a
+/// network. The time is read from it at startup and written back whenever the clock is set.
+///
+/// The real time clock has no time zone, so it keeps `steady_now`, which is UTC when the clock has a
+/// time zone. Daylight saving time starting or ending while the Pi is off then doesn't leave the
+/// time an hour out.
+pub struct RtcClock<D> {
+	clock: Arc<dyn Clock>,
+	rtc: Mutex<Ds3231<D>>,
+}
+
+impl<D> RtcClock<D> where
+	D: I2cDevice + Send,
+	D::Error: Debug,
+{
+	/// Set `clock` from the real time clock, unless it has lost track of the time.
+	pub fn new(clock: Arc<dyn Clock>, mut rtc: Ds3231<D>) -> Self {
+		match rtc.read_time() {
+			Ok(Some(steady)) => clock.set(clock.steady_to_local(steady)),
+			Ok(None) => eprintln!("Real time clock lost the time, keeping the system time"),
+			Err(e) => eprintln!("Unable to read real time clock: {:?}", e),
+		}
+
+		Self { clock, rtc: Mutex::new(rtc) }
+	}
+}
+
+impl<D> Clock for RtcClock<D> where
+	D: I2cDevice + Send,
+	D::Error: Debug,
+{
+	fn now(&self) -> NaiveDateTime {
+		self.clock.now()
+	}
+
+	fn set(&self, now: NaiveDateTime) {
+		self.clock.set(now);
+
+		if let Err(e) = self.rtc.lock().write_time(self.clock.steady_now()) {
+			eprintln!("Unable to set real time clock: {:?}", e);
+		}
+	}
+
+	fn offset(&self) -> chrono::Duration {
+		self.clock.offset()
+	}
+
+	fn skipped(&self, before: NaiveDateTime, now: NaiveDateTime) -> chrono::Duration {
+		self.clock.skipped(before, now)
+	}
+
+	fn steady_now(&self) -> NaiveDateTime {
+		self.clock.steady_now()
+	}
+
+	fn steady_to_local(&self, steady: NaiveDateTime) -> NaiveDateTime {
+		self.clock.steady_to_local(steady)
+	}
+}
+
+/// Clock which only moves when told to.
+#[cfg(test)]
+pub struct VirtualClock {
+	now: RwLock<NaiveDateTime>,
+}
+
+#[cfg(test)]
+impl VirtualClock {
+	pub fn new(now: NaiveDateTime) -> Self {
+		Self { now: RwLock::new(now) }
+	}
+
+	pub fn advance(&self, duration: std::time::Duration) {
+		*self.now.write() += chrono::Duration::from_std(duration).unwrap();
+	}
+}
+
+#[cfg(test)]
+impl Clock for VirtualClock {
+	fn now(&self) -> NaiveDateTime {
+		*self.now.read()
+	}
+
+	fn set(&self, now: NaiveDateTime) {
+		*self.now.write() = now;
+	}
+}
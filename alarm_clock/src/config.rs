@@ -1,15 +1,212 @@
-use serde::Deserialize;
+use std::fs;
+use std::path::{ Path, PathBuf };
 
+use chrono::format::{ Item, StrftimeItems };
+use chrono_tz::Tz;
+
+use serde::{ de, Deserialize, Deserializer };
+
+use crate::alarm::Challenge;
+use crate::circuit::{ Font, BACKPACK_CHARS };
+use crate::time_format::{ AmPmMarker, ClockFace, Separator };
+
+/// Settings read from config.toml. Settings added since the first version have defaults which keep
+/// the clock working as it did before them, so older config files still load.
 #[derive(Deserialize)]
 pub struct Config {
 	buzzer_pin: u8,
 	button_pins: Vec<u8>,
 	midi_dir: String,
+	#[serde(default)]
+	state_file: Option<String>,
 	button_bounce_ms: u64,
+	#[serde(default = "default_display_bus")]
+	display_bus: u8,
+	#[serde(default = "default_display_addresses", deserialize_with = "deserialize_display_addresses")]
+	display_addresses: Vec<u16>,
 	pub brightness: u8,
 	pub scroll_delay_ms: u64,
 	pub ascii_uppercase: bool,
 	pub text_offset: i8,
+	#[serde(default)]
+	font_file: Option<String>,
+	#[serde(default = "default_clock_faces")]
+	pub clock_faces: Vec<ClockFace>,
+	#[serde(default = "default_clock_face_seconds")]
+	pub clock_face_seconds: u32,
+	#[serde(default = "default_date_format", deserialize_with = "deserialize_date_format")]
+	pub date_format: String,
+	#[serde(default = "default_time_separator")]
+	pub time_separator: Separator,
+	#[serde(default)]
+	pub twelve_hour: bool,
+	#[serde(default = "default_am_pm_marker")]
+	pub am_pm_marker: AmPmMarker,
+	#[serde(default = "default_am_pm_every_seconds")]
+	pub am_pm_every_seconds: u32,
+	#[serde(default = "default_am_pm_ms")]
+	pub am_pm_ms: u64,
+	#[serde(default = "default_snooze_minutes")]
+	pub snooze_minutes: u32,
+	#[serde(default = "default_max_snoozes")]
+	pub max_snoozes: u32,
+	#[serde(default = "default_alarm_grace_minutes")]
+	pub alarm_grace_minutes: u32,
+	#[serde(default = "default_dismiss_challenge")]
+	pub dismiss_challenge: Challenge,
+	#[serde(default = "default_hold_seconds")]
+	pub hold_seconds: u32,
+	#[serde(default = "default_timer_song")]
+	timer_song: PathBuf,
+	#[serde(default = "default_nap_song")]
+	nap_song: PathBuf,
+	#[serde(default = "default_nap_minutes")]
+	pub nap_minutes: u32,
+	#[serde(default, deserialize_with = "deserialize_time_zone")]
+	time_zone: Option<Tz>,
+	#[serde(default)]
+	pub rtc: bool,
+	#[serde(default = "default_wake_speed")]
+	pub wake_speed: f64,
+	#[serde(default = "default_wake_max_notes")]
+	pub wake_max_notes: usize,
+	#[serde(default = "default_sleep_slowdown_minutes")]
+	pub sleep_slowdown_minutes: u32,
+	#[serde(default = "default_sleep_slowest_speed")]
+	pub sleep_slowest_speed: f64,
+	#[serde(default)]
+	pub escalation: Vec<Escalation>,
+}
+
+fn default_display_bus() -> u8 {
+	1
+}
+
+fn default_display_addresses() -> Vec<u16> {
+	vec![0x70]
+}
+
+fn default_clock_faces() -> Vec<ClockFace> {
+	vec![ClockFace::HoursMinutes]
+}
+
+fn default_clock_face_seconds() -> u32 {
+	5
+}
+
+fn default_date_format() -> String {
+	"%a %-d %b".to_owned()
+}
+
+fn default_time_separator() -> Separator {
+	Separator::None
+}
+
+fn default_am_pm_marker() -> AmPmMarker {
+	AmPmMarker::Text
+}
+
+fn default_am_pm_every_seconds() -> u32 {
+	10
+}
+
+fn default_am_pm_ms() -> u64 {
+	750
+}
+
+fn default_snooze_minutes() -> u32 {
+	9
+}
+
+fn default_max_snoozes() -> u32 {
+	3
+}
+
+fn default_alarm_grace_minutes() -> u32 {
+	15
+}
+
+fn default_dismiss_challenge() -> Challenge {
+	Challenge::None
+}
+
+fn default_hold_seconds() -> u32 {
+	3
+}
+
+fn default_timer_song() -> PathBuf {
+	PathBuf::from("Mario Theme.mid")
+}
+
+fn default_nap_song() -> PathBuf {
+	PathBuf::from("Still Alive.mid")
+}
+
+fn default_nap_minutes() -> u32 {
+	20
+}
+
+fn default_wake_speed() -> f64 {
+	0.75
+}
+
+fn default_wake_max_notes() -> usize {
+	1
+}
+
+fn default_sleep_slowdown_minutes() -> u32 {
+	5
+}
+
+fn default_sleep_slowest_speed() -> f64 {
+	0.6
+}
+
+/// There has to be at least one display backpack to show anything on.
+fn deserialize_display_addresses<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u16>, D::Error> {
+	let addresses = Vec::<u16>::deserialize(deserializer)?;
+
+	if addresses.is_empty() {
+		return Err(de::Error::custom("display_addresses needs at least one address"));
+	}
+
+	Ok(addresses)
+}
+
+/// Date formats are checked as the config is loaded, since showing the date with a bad one panics.
+fn deserialize_date_format<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+	let format = String::deserialize(deserializer)?;
+
+	if StrftimeItems::new(&format).any(|item| item == Item::Error) {
+		return Err(de::Error::custom(format!("invalid date format \"{}\"", format)));
+	}
+
+	Ok(format)
+}
+
+/// Time zones are checked as the config is loaded, so a typo stops the clock from starting.
+fn deserialize_time_zone<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Tz>, D::Error> {
+	Option::<String>::deserialize(deserializer)?
+		.map(|name| name.parse().map_err(|_| de::Error::custom(format!("unknown time zone \"{}\"", name))))
+		.transpose()
+}
+
+/// Step taken when a ringing alarm hasn't been dismissed after some time.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Escalation {
+	/// Switch to another song, found in `midi_dir`.
+	Song { after_minutes: u32, song: PathBuf },
+	/// Stop the alarm.
+	Stop { after_minutes: u32 },
+}
+
+impl Escalation {
+	pub fn after_minutes(&self) -> u32 {
+		match self {
+			Escalation::Song { after_minutes, .. } | Escalation::Stop { after_minutes } => *after_minutes,
+		}
+	}
 }
 
 impl Config {
@@ -21,10 +218,67 @@ impl Config {
 		&self.button_pins
 	}
 
+	/// I2C bus the display backpacks are on.
+	pub fn display_bus(&self) -> u8 {
+		self.display_bus
+	}
+
+	/// I2C addresses of the display backpacks, from left to right.
+	pub fn display_addresses(&self) -> &[u16] {
+		&self.display_addresses
+	}
+
+	/// Characters shown across all of the display backpacks.
+	pub fn display_width(&self) -> usize {
+		self.display_addresses.len() * BACKPACK_CHARS
+	}
+
 	pub fn midi_dir(&self) -> &str {
 		&self.midi_dir
 	}
 
+	/// File to keep alarms and the clock setting in, or `None` to not keep them across restarts.
+	pub fn state_file(&self) -> Option<&str> {
+		self.state_file.as_deref()
+	}
+
+	/// Font for the display, or just the built-in characters if no font file is set or it can't be
+	/// loaded.
+	pub fn font(&self) -> Font {
+		let font_file = match &self.font_file {
+			Some(font_file) => font_file,
+			None => return Font::default(),
+		};
+
+		let text = match fs::read_to_string(font_file) {
+			Ok(text) => text,
+			Err(e) => {
+				eprintln!("Unable to read font file \"{}\", using built-in characters: {}", font_file, e);
+				return Font::default();
+			}
+		};
+
+		toml::from_str(&text).unwrap_or_else(|e| {
+			eprintln!("Unable to parse font file \"{}\", using built-in characters: {}", font_file, e);
+			Font::default()
+		})
+	}
+
+	/// Song the countdown timer plays when it ends.
+	pub fn timer_song(&self) -> PathBuf {
+		Path::new(&self.midi_dir).join(&self.timer_song)
+	}
+
+	/// Song a nap alarm plays when it goes off.
+	pub fn nap_song(&self) -> PathBuf {
+		Path::new(&self.midi_dir).join(&self.nap_song)
+	}
+
+	/// Time zone to show the system clock in, instead of keeping time from the local time at startup.
+	pub fn time_zone(&self) -> Option<Tz> {
+		self.time_zone
+	}
+
 	pub fn button_bounce_ms(&self) -> u64 {
 		self.button_bounce_ms
 	}
@@ -1,110 +1,217 @@
-use std::convert::TryInto;
-use std::fs;
-use std::sync::mpsc;
+use std::convert::TryFrom;
+use std::fs::{ self, File };
+use std::io::BufWriter;
+use std::sync::{ mpsc, Arc };
 use std::path::{ Path, PathBuf };
 use std::thread;
-use std::time::{ Instant, Duration };
 
-use chrono::{NaiveTime, offset::Local};
+use chrono::{ NaiveTime, Timelike };
 
 use once_cell::sync::Lazy;
 
 use parking_lot::RwLock;
 
+use serde::{ Deserialize, Serialize };
+
+mod alarm;
+use alarm::{ Alarm, Ringing, SleepTimer, Timer };
+
+mod clock;
+use clock::{ Clock, RealClock, RtcClock, ZonedClock };
+
 mod circuit;
 use circuit::Buzzer;
-use circuit::Button;
+use circuit::Buttons;
 use circuit::Alphanum;
+use circuit::Ds3231;
+use circuit::{ AlphanumDisplay, InputSource, ToneOutput };
+use circuit::terminal::{ TerminalAlphanum, TerminalBuzzer, TerminalInput };
 
 mod config;
 use config::Config;
 
 mod message;
-use message::{ EventMessage, SongEvent };
+use message::{ AlarmEvent, EventMessage, SongEvent };
 
 mod note;
 use note::MidiNote;
 
+mod saved;
+use saved::SavedState;
+
 mod selector;
 
 mod states;
-use states::{
-	StateId,
-	State,
-	StateClock,
-	StateModeSelect,
-	StateClockSet,
-	StateAlarmTimeSet,
-	StateAlarmSongSet,
-	StatePlay,
-};
+use states::{ StateId, StateMachine };
 
 mod threads;
 use threads::input::poll_inputs;
 use threads::buzzer::update_buzzer;
-use threads::player::midi_player;
+use threads::player::{ midi_player, song_messages };
 use threads::alphanum::alphanum_thread;
 use threads::alarm::alarm_thread;
+use threads::save::save_thread;
+
+mod time_format;
+use time_format::pair_chars;
+
+mod wav;
+use wav::WavRenderer;
 
 #[cfg(test)] mod tests;
+#[cfg(test)] mod scenario;
 
-static TIME_ZERO: Lazy<RwLock<Instant>> = Lazy::new(|| {
-	RwLock::new(Instant::now() - (Local::now().time() - NaiveTime::from_hms(0, 0, 0)).to_std().unwrap_or(Duration::ZERO))
-});
-static ALARM_TIME: Lazy<RwLock<Option<ClockTime>>> = Lazy::new(|| RwLock::new(None));
-static ALARM_SONG: Lazy<RwLock<Option<PathBuf>>> = Lazy::new(|| RwLock::new(None));
+static ALARMS: Lazy<RwLock<Vec<Alarm>>> = Lazy::new(|| RwLock::new(Vec::new()));
+static RINGING: Lazy<RwLock<Option<Ringing>>> = Lazy::new(|| RwLock::new(None));
+static TIMER: Lazy<RwLock<Option<Timer>>> = Lazy::new(|| RwLock::new(None));
+static NAP: Lazy<RwLock<Option<Timer>>> = Lazy::new(|| RwLock::new(None));
+static SLEEP: Lazy<RwLock<Option<SleepTimer>>> = Lazy::new(|| RwLock::new(None));
 static CONFIG: Lazy<RwLock<Config>> = Lazy::new(|| {
 	let config_file = "config.toml";
 
 	RwLock::new(
 		toml::from_str(
 			&fs::read_to_string(config_file)
-				.expect(&format!("Unable to read config file \"{}\"", config_file))
+				.unwrap_or_else(|_| panic!("Unable to read config file \"{}\"", config_file))
 		).expect("Unable to parse config file")
 	)
 });
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Time of day to the second.
+///
+/// Saved as text like "07:30", or "07:30:15" when there are seconds. Whole numbers from older state
+/// files are read as minutes since midnight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "SavedTime", into = "String")]
 pub struct ClockTime {
-	minutes: u16
+	seconds: u32
 }
 
 impl ClockTime {
+	/// Time of day at a whole number of minutes since midnight.
 	pub fn new(minutes: u16) -> Self {
-		Self { minutes: minutes % (24 * 60) }
+		Self::from_seconds(minutes as u32 * 60)
+	}
+
+	pub fn from_seconds(seconds: u32) -> Self {
+		Self { seconds: seconds % (24 * 60 * 60) }
 	}
 
-	pub fn now() -> Self {
-		Self::new((((Instant::now() - *TIME_ZERO.read()).as_secs() / 60) % (24 * 60)) as u16)
+	pub fn seconds_from_midnight(&self) -> u32 {
+		self.seconds
 	}
 
 	pub fn hours(&self) -> u8 {
-		(self.minutes / 60) as u8
+		(self.seconds / (60 * 60)) as u8
 	}
 
 	pub fn minutes(&self) -> u8 {
-		(self.minutes % 60) as u8
+		(self.seconds / 60 % 60) as u8
+	}
+
+	pub fn seconds(&self) -> u8 {
+		(self.seconds % 60) as u8
+	}
+
+	pub fn is_pm(&self) -> bool {
+		self.hours() >= 12
 	}
 
+	/// Hours and minutes, in 12 or 24 hour time as set in the config.
 	pub fn as_chars(&self) -> [char; 4] {
-		format!("{:02}{:02}", self.hours(), self.minutes())
-			.chars()
-			.collect::<Vec<_>>()
-			.try_into()
-			.unwrap()
+		self.format_chars(CONFIG.read().twelve_hour)
+	}
+
+	pub fn format_chars(&self, twelve_hour: bool) -> [char; 4] {
+		if !twelve_hour {
+			return pair_chars(self.hours().into(), self.minutes().into());
+		}
+
+		let hours = match self.hours() % 12 {
+			0 => 12,
+			hours => hours,
+		};
+
+		let mut chars = pair_chars(hours.into(), self.minutes().into());
+		if chars[0] == '0' {
+			chars[0] = ' ';
+		}
+
+		chars
+	}
+
+	/// The time for scrolling text, followed by AM or PM in 12 hour time.
+	pub fn text(&self) -> String {
+		self.format_text(CONFIG.read().twelve_hour)
+	}
+
+	pub fn format_text(&self, twelve_hour: bool) -> String {
+		let digits = self.format_chars(twelve_hour).iter().collect::<String>();
+
+		if twelve_hour {
+			format!("{} {}", digits.trim_start(), if self.is_pm() { "PM" } else { "AM" })
+		} else {
+			digits
+		}
+	}
+
+	/// AM or PM, shown briefly alongside 12 hour times.
+	pub fn am_pm_chars(&self) -> [char; 4] {
+		[' ', if self.is_pm() { 'P' } else { 'A' }, 'M', ' ']
+	}
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedTime {
+	Minutes(u16),
+	Text(String),
+}
+
+impl TryFrom<SavedTime> for ClockTime {
+	type Error = String;
+
+	fn try_from(saved: SavedTime) -> Result<Self, Self::Error> {
+		match saved {
+			SavedTime::Minutes(minutes) => Ok(Self::new(minutes)),
+			SavedTime::Text(text) => NaiveTime::parse_from_str(&text, "%H:%M:%S")
+				.or_else(|_| NaiveTime::parse_from_str(&text, "%H:%M"))
+				.map(Self::from)
+				.map_err(|_| format!("Expected time like 07:30 or 07:30:15, found {:?}", text)),
+		}
 	}
 }
 
-impl From<ClockTime> for Duration {
+impl From<ClockTime> for String {
 	fn from(clock_time: ClockTime) -> Self {
-		Duration::from_secs(clock_time.minutes as u64 * 60)
+		match clock_time.seconds() {
+			0 => format!("{:02}:{:02}", clock_time.hours(), clock_time.minutes()),
+			seconds => format!("{:02}:{:02}:{:02}", clock_time.hours(), clock_time.minutes(), seconds),
+		}
+	}
+}
+
+impl From<NaiveTime> for ClockTime {
+	fn from(time: NaiveTime) -> Self {
+		Self::from_seconds(time.num_seconds_from_midnight())
+	}
+}
+
+impl From<ClockTime> for NaiveTime {
+	fn from(clock_time: ClockTime) -> Self {
+		NaiveTime::from_num_seconds_from_midnight(clock_time.seconds, 0)
 	}
 }
 
 #[derive(Debug)]
+#[allow(dead_code)]
 enum Error {
 	Gpio(rppal::gpio::Error),
 	I2c(rppal::i2c::Error),
+	Io(std::io::Error),
+	Midi(midly::Error),
+	/// Command line arguments which don't make sense.
+	Usage(&'static str),
 }
 
 impl From<rppal::gpio::Error> for Error {
@@ -119,22 +226,113 @@ impl From<rppal::i2c::Error> for Error {
 	}
 }
 
+impl From<midly::Error> for Error {
+	fn from(from: midly::Error) -> Self {
+		Self::Midi(from)
+	}
+}
+
+impl From<std::io::Error> for Error {
+	fn from(from: std::io::Error) -> Self {
+		Self::Io(from)
+	}
+}
+
 fn main() -> Result<(), Error> {
-	Lazy::force(&TIME_ZERO);
+	let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+	if args.iter().any(|arg| arg == "--render") {
+		return match args.as_slice() {
+			[flag, midi_file, wav_file] if flag == "--render" => render(midi_file, wav_file),
+			_ => Err(Error::Usage("Expected --render <midi file> <wav file>")),
+		};
+	}
+
 	Lazy::force(&CONFIG);
 
+	let saved = CONFIG.read().state_file().map(SavedState::load).unwrap_or_default();
+	*ALARMS.write() = saved.alarms.clone();
+
+	let clock: Arc<dyn Clock> = match CONFIG.read().time_zone() {
+		Some(time_zone) => Arc::new(ZonedClock::new(time_zone, saved.clock_offset())),
+		None => Arc::new(RealClock::new(saved.clock_offset())),
+	};
+
+	let simulating = args.iter().any(|arg| arg == "--simulate");
+
+	let clock: Arc<dyn Clock> = if CONFIG.read().rtc && !simulating {
+		Arc::new(RtcClock::new(clock, Ds3231::on_i2c(CONFIG.read().display_bus())?))
+	} else {
+		clock
+	};
+
+	// start thread to save alarms and clock settings whenever they change
+	let _thread_save = CONFIG.read().state_file().map(|path| {
+		let path = PathBuf::from(path);
+		let clock = clock.clone();
+		thread::spawn(move || save_thread(path, saved, clock))
+	});
+
+	if simulating {
+		return simulate(clock);
+	}
+
 	// create buzzer controller
 	let buzzer = Buzzer::<MidiNote>::new(CONFIG.read().buzzer_pin())?;
 
 	// create button pollers
-	let buttons = CONFIG.read().button_pins().iter()
-		.map(|&p| Button::new(p).map_err(|e| e.into()))
-		.collect::<Result<Vec<_>, Error>>()?;
+	let buttons = Buttons::new(CONFIG.read().button_pins())?;
 
 	// create alphanum controller
-	let mut alphanum = Alphanum::new()?;
+	let alphanum = {
+		let config = CONFIG.read();
+		Alphanum::new(config.display_bus(), config.display_addresses())?
+	};
+
+	run(clock, buzzer, buttons, alphanum)?;
+
+	Ok(())
+}
+
+/// Play a midi file into a WAV file of what the buzzer would sound like. The song is walked in
+/// virtual time, so it renders as fast as it can be read and comes out the same every time.
+fn render(midi_file: &str, wav_file: &str) -> Result<(), Error> {
+	let midi = fs::read(midi_file)?;
+	let smf = midly::Smf::parse(&midi)?;
+
+	let renderer = WavRenderer::render_messages(song_messages(&smf));
+	renderer.write(BufWriter::new(File::create(wav_file)?))?;
+
+	Ok(())
+}
+
+/// Run the clock in the terminal instead of on the Raspberry Pi hardware.
+fn simulate(clock: Arc<dyn Clock>) -> Result<(), Error> {
+	circuit::terminal::init()?;
+
+	let result = run(clock, TerminalBuzzer::new(), TerminalInput::new(), TerminalAlphanum::new(CONFIG.read().display_width()));
+
+	circuit::terminal::restore();
+
+	Ok(result?)
+}
+
+/// Run the clock on the given hardware until the state machine stops.
+fn run<T, I, A>(
+	clock: Arc<dyn Clock>,
+	buzzer: T,
+	input_source: I,
+	mut alphanum: A,
+) -> Result<(), A::Error> where
+	T: ToneOutput<MidiNote> + Send + 'static,
+	I: InputSource + Send + 'static,
+	I::Error: Send + 'static,
+	A: AlphanumDisplay + Send + 'static,
+	A::Error: Send + 'static,
+{
 	alphanum.set_brightness(CONFIG.read().brightness)?;
 	alphanum.ascii_uppercase(CONFIG.read().ascii_uppercase);
+	alphanum.font(CONFIG.read().font());
 
 	// create channels for messages
 	let (midi_note_sender, midi_note_receiver) = mpsc::channel();
@@ -148,7 +346,17 @@ fn main() -> Result<(), Error> {
 	// start thread to read poll button
 	let _thread_buttons = {
 		let event_sender_2 = event_sender.clone();
-		thread::spawn(move || { poll_inputs(event_sender_2, buttons) })
+		thread::spawn(move || { poll_inputs(event_sender_2, input_source) })
+	};
+
+	// start alarm thread
+	let _alarm_thread = {
+		let player_sender = player_sender.clone();
+		let event_sender = event_sender.clone();
+		let clock = clock.clone();
+		thread::spawn(move ||
+			alarm_thread(player_sender, event_sender, clock)
+		)
 	};
 
 	// start playing midi file
@@ -157,69 +365,51 @@ fn main() -> Result<(), Error> {
 	);
 
 	// start display thread
-	let _thread_display = thread::spawn(move ||
-		alphanum_thread(alphanum, alphanum_receiver)
-	);
-
-	// start alarm thread
-	let _alarm_thread = {
-		let player_sender = player_sender.clone();
+	let _thread_display = {
+		let clock = clock.clone();
 		thread::spawn(move ||
-			alarm_thread(player_sender)
+			alphanum_thread(alphanum, alphanum_receiver, clock)
 		)
 	};
 
 	let mut midi_files = list_files(&CONFIG.read().midi_dir())
-		.expect(&format!("Unable to read the directory \"{:?}\"", CONFIG.read().midi_dir()))
+		.unwrap_or_else(|_| panic!("Unable to read the directory \"{:?}\"", CONFIG.read().midi_dir()))
 		.collect::<Vec<PathBuf>>();
 	midi_files.sort();
 
-	// default initialize alarm song
-	*ALARM_SONG.write() = midi_files.first().cloned();
-
-	let mut state_id = StateId::Clock;
-
-	loop {
-		println!("Entering state {:?}", state_id);
+	// songs saved with alarms may have been removed since
+	for alarm in ALARMS.write().iter_mut() {
+		if alarm.song.as_ref().is_some_and(|song| !midi_files.contains(song)) {
+			alarm.song = midi_files.first().cloned();
+		}
+	}
 
-		let mut state: Box<dyn State> = match state_id {
-			StateId::Clock => Box::new(StateClock::new(alphanum_sender.clone(), player_sender.clone())),
-			StateId::ModeSelect => Box::new(StateModeSelect::new(alphanum_sender.clone())),
-			StateId::ClockSet => Box::new(StateClockSet::new(alphanum_sender.clone())),
-			StateId::AlarmTime => Box::new(StateAlarmTimeSet::new(alphanum_sender.clone())),
-			StateId::AlarmSong => Box::new(StateAlarmSongSet::new(alphanum_sender.clone(), player_sender.clone(), midi_files.clone())),
-			StateId::Play => Box::new(StatePlay::new(alphanum_sender.clone(), player_sender.clone(), midi_files.clone())),
-			StateId::Bad => break,
-		};
+	let mut state_machine = StateMachine::new(alphanum_sender, player_sender, clock, midi_files);
 
-		state.init();
-
-		state_id = loop {
-			match event_receiver.recv() {
-				Ok(msg) => {
-					match &msg {
-						EventMessage::Song(event) => match event {
-							SongEvent::Start(name) => {
-								println!("Now playing {:?}", name);
-							}
-							SongEvent::End(name) => {
-								println!("Stopped playing {:?}", name);
-							}
-						}
-						_ => (),
-					}
-
-					if let Some(next_state) = state.event(msg) {
-						break next_state;
-					}
-				}
-				Err(_) => break StateId::Bad,
+	while let Ok(msg) = event_receiver.recv() {
+		match &msg {
+			EventMessage::Song(SongEvent::Start(name)) => {
+				println!("Now playing {:?}", name);
 			}
-		};
+			EventMessage::Song(SongEvent::End(name)) => {
+				println!("Stopped playing {:?}", name);
+			}
+			EventMessage::Alarm(AlarmEvent::Escalate(name)) => {
+				println!("Alarm escalated to {:?}", name);
+			}
+			EventMessage::Alarm(AlarmEvent::Stop) => {
+				println!("Alarm stopped itself");
+			}
+			EventMessage::Button(_) | EventMessage::Tick => (),
+		}
 
-		state.finish();
+		if let Some(StateId::Bad) = state_machine.event(msg) {
+			break;
+		}
 	}
 
+	state_machine.stop();
+
 	//println!("{} updates per second", thread_buzzer.join().unwrap()?);
 	//thread_midi_player.join().unwrap();
 	//thread_buttons.join().unwrap()?;
@@ -1,15 +1,20 @@
 use std::path::PathBuf;
 
+use chrono::NaiveDateTime;
+
 use crate::note::MidiNote;
-use crate::circuit::BlinkRate;
+use crate::circuit::{ BlinkRate, Glyph };
 
 #[derive(Debug)]
 pub enum EventMessage {
 	Button(ButtonEvent),
 	Song(SongEvent),
+	Alarm(AlarmEvent),
+	/// Sent by the alarm thread every second, for states waiting on time to pass.
+	Tick,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ButtonEvent {
 	Press(u8),
 	Release(u8),
@@ -21,6 +26,21 @@ pub enum SongEvent {
 	End(String),
 }
 
+/// Raised when a ringing alarm escalates because nobody dismissed it.
+#[derive(Debug)]
+pub enum AlarmEvent {
+	/// The alarm switched to a harsher song.
+	Escalate(String),
+	/// The alarm stopped ringing by itself.
+	Stop,
+}
+
+impl From<AlarmEvent> for EventMessage {
+	fn from(from: AlarmEvent) -> Self {
+		EventMessage::Alarm(from)
+	}
+}
+
 impl From<SongEvent> for EventMessage {
 	fn from(from: SongEvent) -> Self {
 		EventMessage::Song(from)
@@ -43,14 +63,29 @@ pub enum BuzzerMessage {
 pub enum PlayerMessage {
 	Loop(PathBuf),
 	Play(PathBuf),
+	/// Loop a quieter, sparser and slower version of a song, to wake up to gently.
+	Gentle(PathBuf),
+	/// Change how fast songs play, where 1 is normal speed. Stopping goes back to normal speed.
+	Speed(f64),
 	Stop,
 }
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub enum AlphanumMessage {
-	Static([char; 4]),
+	/// Text shown from the left of the display, cut short if it is wider.
+	Static(String),
+	/// Characters with their decimal points, shown as they are.
+	Glyphs(Vec<Glyph>),
+	/// Text scrolled along, where a '.' lights the decimal point of the character before it.
 	Loop(String),
+	/// Text with a marker, like AM or PM, shown briefly in place of it now and then.
+	Marked(String, String),
 	Time,
+	/// Time left until the given time.
+	Countdown(NaiveDateTime),
+	/// Time since the given time, like a stopwatch.
+	Elapsed(NaiveDateTime),
 	Empty,
 	Blink(BlinkRate),
 }
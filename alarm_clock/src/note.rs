@@ -3,12 +3,13 @@ use pre_table::freq_table;
 freq_table!(F);
 
 pub trait Note {
+	#[allow(dead_code)]
 	const A0: f64 = 27.5;
 
 	fn frequency(&self) -> f64;
 }
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct MidiNote(pub i8);
 
 impl Note for MidiNote {
@@ -16,3 +17,14 @@ impl Note for MidiNote {
 		F[(self.0 as u8) as usize]
 	}
 }
+
+impl MidiNote {
+	/// Scientific pitch name of the note, where middle C is "C4".
+	pub fn name(&self) -> String {
+		const NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+		let key = self.0 as i16;
+
+		format!("{}{}", NAMES[key.rem_euclid(12) as usize], key.div_euclid(12) - 1)
+	}
+}
@@ -0,0 +1,64 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{ Deserialize, Serialize };
+
+use crate::ALARMS;
+use crate::alarm::Alarm;
+use crate::clock::Clock;
+
+/// Settings changed while the clock runs, kept in the state file so they survive a restart.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedState {
+	/// Seconds the clock is ahead of the system clock.
+	pub clock_offset: i64,
+	pub alarms: Vec<Alarm>,
+}
+
+impl SavedState {
+	/// Take the current settings from the running clock.
+	pub fn current(clock: &dyn Clock) -> Self {
+		let offset = clock.offset();
+
+		Self {
+			clock_offset: (offset.num_milliseconds() as f64 / 1000.0).round() as i64,
+			alarms: ALARMS.read().clone(),
+		}
+	}
+
+	/// Read the state file, falling back to the defaults if it is missing or can't be read.
+	pub fn load<P: AsRef<Path>>(path: P) -> Self {
+		let path = path.as_ref();
+
+		let text = match fs::read_to_string(path) {
+			Ok(text) => text,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Self::default(),
+			Err(e) => {
+				eprintln!("Unable to read state file {:?}, using defaults: {}", path, e);
+				return Self::default();
+			}
+		};
+
+		toml::from_str(&text).unwrap_or_else(|e| {
+			eprintln!("Unable to parse state file {:?}, using defaults: {}", path, e);
+			Self::default()
+		})
+	}
+
+	/// Write the state file, replacing it only once the new one is complete.
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		let path = path.as_ref();
+		let text = toml::to_string(self)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+		let temp_path = path.with_extension("tmp");
+		fs::write(&temp_path, text)?;
+		fs::rename(temp_path, path)
+	}
+
+	pub fn clock_offset(&self) -> chrono::Duration {
+		chrono::Duration::seconds(self.clock_offset)
+	}
+}
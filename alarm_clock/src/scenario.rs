@@ -0,0 +1,511 @@
+//! Scripted scenarios run against the state machine.
+//!
+//! A scenario is a text file with one command per line. Blank lines and lines starting with `#`
+//! are ignored. Inputs are fed to the state machine, and everything it outputs is recorded in
+//! three queues: display messages, player messages and states entered. Each `expect` takes the
+//! oldest output from its queue, and any output not expected before the next input or the end of
+//! the scenario fails it.
+//!
+//! ```text
+//! press <button> [xN]            button is down, up, mode or a number
+//! release <button> [xN]
+//! tap <button> [xN]              press then release
+//! song start <name>
+//! song end <name>
+//! clock <hh:mm>                  set the time of day, ringing and escalating alarms and
+//!                                running the sleep timer
+//! date <yyyy-mm-dd>              set the date, also ringing and escalating alarms
+//! mode <mode>                    pick a mode from the clock, e.g. Alarms, without expecting
+//!                                the mode select output on the way
+//! alarm <hh:mm> [song]           add an alarm, with the first song unless one is named
+//! alarm <n> repeat <days>        change an alarm without going through its menu
+//! alarm <n> dismiss <challenge>
+//! alarm <n> wake <minutes>
+//!
+//! expect state <state>           e.g. ModeSelect
+//! expect display time
+//! expect display empty
+//! expect display countdown <hh:mm:ss>  time the countdown ends
+//! expect display elapsed <hh:mm:ss>    time the stopwatch was started from
+//! expect display static <text>   text, quoted if there are spaces
+//! expect display glyphs <text>   characters with '.' after those with their decimal point lit
+//! expect display marked <text>   text then the marker, like " 730  PM "
+//! expect display loop <text>
+//! expect display blink <rate>    e.g. OneHz
+//! expect player play <song>      songs are named by their file stem
+//! expect player loop <song>
+//! expect player gentle <song>
+//! expect player stop
+//! expect player speed <speed>    e.g. 0.9
+//! expect clock <hh:mm>
+//! expect date <yyyy-mm-dd>
+//! expect alarms <count>
+//! expect alarm <n> time <hh:mm>  alarms are numbered from 1, as on the display
+//! expect alarm <n> song <song>
+//! expect alarm <n> repeat <days>  e.g. Weekdays, or Mon Wed for custom days
+//! expect alarm <n> dismiss <challenge>  e.g. Sequence, or Default for the one in the config
+//! expect alarm <n> wake <minutes>  e.g. 10m, or Off
+//! expect alarm <n> on
+//! expect alarm <n> off
+//! ```
+
+use std::collections::VecDeque;
+use std::fs;
+use std::mem;
+use std::path::{ Path, PathBuf };
+use std::sync::{ mpsc, Arc };
+
+use chrono::{ NaiveDate, NaiveDateTime };
+
+use crate::{ ALARMS, CONFIG, NAP, RINGING, SLEEP, TIMER, ClockTime };
+use crate::alarm::{ Alarm, Challenge };
+use crate::circuit::glyph_text;
+use crate::clock::{ Clock, VirtualClock };
+use crate::config::Config;
+use crate::message::{ AlphanumMessage, ButtonEvent, EventMessage, PlayerMessage, SongEvent };
+use crate::states::{ StateId, StateMachine };
+use crate::threads::alarm::{ alarm_song, escalate, sleep_update, wake_song };
+use crate::threads::player::song_name;
+
+/// Settings scenarios are written against, so they don't change with the config the clock ships with.
+const SCENARIO_CONFIG: &str = r#"
+	buzzer_pin = 12
+	button_pins = [ 5, 6, 13 ]
+	midi_dir = "../midi/"
+	button_bounce_ms = 200
+	brightness = 7
+	scroll_delay_ms = 250
+	ascii_uppercase = true
+	text_offset = -2
+
+	snooze_minutes = 9
+	max_snoozes = 3
+	alarm_grace_minutes = 15
+	dismiss_challenge = "none"
+	hold_seconds = 3
+	timer_song = "Mario Theme.mid"
+	nap_song = "Still Alive.mid"
+	nap_minutes = 20
+	wake_speed = 0.75
+	sleep_slowdown_minutes = 5
+	sleep_slowest_speed = 0.6
+
+	[[escalation]]
+	action = "song"
+	after_minutes = 10
+	song = "Toccata and Fugue.mid"
+
+	[[escalation]]
+	action = "stop"
+	after_minutes = 30
+"#;
+
+/// Songs available to scenarios, as if they were the files in `midi_dir`.
+pub fn midi_files() -> Vec<PathBuf> {
+	let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../midi");
+
+	let mut midi_files = fs::read_dir(&dir)
+		.unwrap_or_else(|_| panic!("Unable to read the directory {:?}", dir))
+		.map(|f| f.unwrap().path())
+		.collect::<Vec<_>>();
+	midi_files.sort();
+
+	midi_files
+}
+
+pub struct Scenario {
+	state_machine: StateMachine,
+	clock: Arc<VirtualClock>,
+	alphanum_receiver: mpsc::Receiver<AlphanumMessage>,
+	player_receiver: mpsc::Receiver<PlayerMessage>,
+	display: VecDeque<AlphanumMessage>,
+	player: VecDeque<PlayerMessage>,
+	states: VecDeque<StateId>,
+	/// Config from config.toml, put back when the scenario ends.
+	device_config: Option<Config>,
+}
+
+impl Scenario {
+	/// Start a new scenario at midnight on Tuesday the first of June 2021, with no alarms.
+	pub fn new() -> Self {
+		let config = toml::from_str(SCENARIO_CONFIG).expect("Unable to parse scenario config");
+		let device_config = mem::replace(&mut *CONFIG.write(), config);
+
+		let (alphanum_sender, alphanum_receiver) = mpsc::channel();
+		let (player_sender, player_receiver) = mpsc::channel();
+		let clock = Arc::new(VirtualClock::new(NaiveDate::from_ymd(2021, 6, 1).and_hms(0, 0, 0)));
+
+		ALARMS.write().clear();
+		RINGING.write().take();
+		TIMER.write().take();
+		NAP.write().take();
+		SLEEP.write().take();
+
+		let state_machine = StateMachine::new(alphanum_sender, player_sender, clock.clone(), midi_files());
+
+		let mut scenario = Self {
+			state_machine,
+			clock,
+			alphanum_receiver,
+			player_receiver,
+			display: VecDeque::new(),
+			player: VecDeque::new(),
+			states: VecDeque::from(vec![StateId::Clock]),
+			device_config: Some(device_config),
+		};
+
+		scenario.record();
+
+		scenario
+	}
+
+	/// Run every line of a script, panicking with the line number on the first failure.
+	pub fn run(&mut self, name: &str, script: &str) {
+		for (i, line) in script.lines().enumerate() {
+			let line = line.trim();
+
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+
+			if let Err(e) = self.command(line) {
+				panic!("{}:{}: {}\n\t{}", name, i + 1, e, line);
+			}
+		}
+
+		if let Err(e) = self.check_expected() {
+			panic!("{}: at end of scenario: {}", name, e);
+		}
+	}
+
+	fn command(&mut self, line: &str) -> Result<(), String> {
+		let words = line.split_whitespace().collect::<Vec<_>>();
+
+		if words.first() != Some(&"expect") {
+			self.check_expected()?;
+		}
+
+		match words.as_slice() {
+			["press", button, repeat @ ..] => self.buttons(button, repeat, &[ButtonEvent::Press]),
+			["release", button, repeat @ ..] => self.buttons(button, repeat, &[ButtonEvent::Release]),
+			["tap", button, repeat @ ..] => self.buttons(button, repeat, &[ButtonEvent::Press, ButtonEvent::Release]),
+			["song", "start", ..] => {
+				self.event(SongEvent::Start(rest(line, 2)).into());
+				Ok(())
+			}
+			["song", "end", ..] => {
+				self.event(SongEvent::End(rest(line, 2)).into());
+				Ok(())
+			}
+			["clock", time] => {
+				let now = self.clock.date().and_time(parse_time(time)?.into());
+				self.set_clock(now);
+				Ok(())
+			}
+			["date", date] => {
+				let date = date.parse::<NaiveDate>()
+					.map_err(|_| format!("Expected date like 2021-06-01, found {:?}", date))?;
+
+				self.set_clock(date.and_time(self.clock.now().time()));
+				Ok(())
+			}
+			["mode", ..] => self.mode(&rest(line, 1)),
+			["alarm", time, ..] if time.contains(':') => {
+				let song = match rest(line, 2).as_str() {
+					"" => midi_files().first().cloned(),
+					name => Some(midi_files().into_iter()
+						.find(|path| song_name(path) == name)
+						.ok_or_else(|| format!("No song {:?}", name))?),
+				};
+
+				ALARMS.write().push(Alarm::new(parse_time(time)?, song));
+				Ok(())
+			}
+			["alarm", n, "repeat", ..] => {
+				let repeat = rest(line, 3).parse()?;
+				self.edit_alarm(n, |alarm| alarm.repeat = repeat)
+			}
+			["alarm", n, "dismiss", ..] => {
+				let challenge = match rest(line, 3).as_str() {
+					"Default" => None,
+					name => Some([Challenge::None, Challenge::Sequence, Challenge::Hold].iter()
+						.copied()
+						.find(|challenge| challenge.to_string() == name)
+						.ok_or_else(|| format!("Unknown challenge {:?}", name))?),
+				};
+
+				self.edit_alarm(n, |alarm| alarm.challenge = challenge)
+			}
+			["alarm", n, "wake", minutes] => {
+				let minutes = match *minutes {
+					"Off" => 0,
+					_ => minutes.strip_suffix('m')
+						.and_then(|m| m.parse().ok())
+						.ok_or_else(|| format!("Expected minutes like 10m, found {:?}", minutes))?,
+				};
+
+				self.edit_alarm(n, |alarm| alarm.wake_minutes = minutes)
+			}
+			["expect", ..] => self.expect(&words[1..], line),
+			_ => Err("Unknown command".to_owned()),
+		}
+	}
+
+	/// Open the mode select and move down to a mode, then enter it.
+	fn mode(&mut self, name: &str) -> Result<(), String> {
+		self.buttons("mode", &[], &[ButtonEvent::Press, ButtonEvent::Release])?;
+
+		if self.states.pop_front() != Some(StateId::ModeSelect) {
+			return Err("Mode select wasn't entered".to_owned());
+		}
+
+		let mut passed = Vec::new();
+
+		loop {
+			match self.display.pop_front() {
+				Some(AlphanumMessage::Loop(text)) if text == name => break,
+				Some(AlphanumMessage::Loop(text)) if !passed.contains(&text) => {
+					passed.push(text);
+					self.buttons("down", &[], &[ButtonEvent::Press, ButtonEvent::Release])?;
+				}
+				Some(AlphanumMessage::Loop(_)) => return Err(format!("No mode {:?}", name)),
+				msg => return Err(format!("Mode select showed {:?}", msg)),
+			}
+		}
+
+		self.check_expected()?;
+		self.buttons("mode", &[], &[ButtonEvent::Press, ButtonEvent::Release])
+	}
+
+	/// Change an alarm numbered from 1, as on the display.
+	fn edit_alarm(&mut self, n: &str, edit: impl FnOnce(&mut Alarm)) -> Result<(), String> {
+		let mut alarms = ALARMS.write();
+		let alarm = n.parse::<usize>().ok()
+			.and_then(|n| alarms.get_mut(n.checked_sub(1)?))
+			.ok_or_else(|| format!("No alarm {}", n))?;
+
+		edit(alarm);
+		Ok(())
+	}
+
+	fn buttons(&mut self, button: &str, repeat: &[&str], events: &[fn(u8) -> ButtonEvent]) -> Result<(), String> {
+		let button = match button {
+			"down" => 0,
+			"up" => 1,
+			"mode" => 2,
+			_ => button.parse().map_err(|_| format!("Unknown button {:?}", button))?,
+		};
+
+		let repeat = match repeat {
+			[] => 1,
+			[repeat] => repeat.strip_prefix('x')
+				.and_then(|r| r.parse().ok())
+				.ok_or_else(|| format!("Expected repeat count like x3, found {:?}", repeat))?,
+			_ => return Err("Too many arguments".to_owned()),
+		};
+
+		for _ in 0..repeat {
+			for event in events {
+				self.event(event(button).into());
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Set the clock, then do what the alarm thread would.
+	fn set_clock(&mut self, now: NaiveDateTime) {
+		let before = self.clock.now();
+		self.clock.set(now);
+		let steady_now = self.clock.steady_now();
+
+		// the alarm thread sends alarms straight to the player
+		if let Some(song) = alarm_song(before, now, self.clock.skipped(before, now), steady_now) {
+			self.player.push_back(PlayerMessage::Loop(song));
+		}
+
+		while let Some((msg, event)) = escalate(steady_now) {
+			self.player.push_back(msg);
+			self.event(event.into());
+		}
+
+		if let Some(song) = wake_song(before, now) {
+			self.player.push_back(PlayerMessage::Gentle(song));
+		}
+
+		if let Some(msg) = sleep_update(steady_now) {
+			self.player.push_back(msg);
+		}
+
+		self.event(EventMessage::Tick);
+	}
+
+	fn event(&mut self, event: EventMessage) {
+		if let Some(state_id) = self.state_machine.event(event) {
+			self.states.push_back(state_id);
+		}
+
+		self.record();
+	}
+
+	fn record(&mut self) {
+		self.display.extend(self.alphanum_receiver.try_iter());
+		self.player.extend(self.player_receiver.try_iter());
+	}
+
+	fn check_expected(&self) -> Result<(), String> {
+		if let Some(msg) = self.display.front() {
+			Err(format!("Unexpected display message {:?}", msg))
+		} else if let Some(msg) = self.player.front() {
+			Err(format!("Unexpected player message {:?}", msg))
+		} else if let Some(state_id) = self.states.front() {
+			Err(format!("Unexpected state {:?}", state_id))
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Alarm numbered from 1, as on the display.
+	fn alarm(&self, n: &str) -> Result<Alarm, String> {
+		n.parse::<usize>().ok()
+			.and_then(|n| ALARMS.read().get(n.checked_sub(1)?).cloned())
+			.ok_or_else(|| format!("No alarm {}", n))
+	}
+
+	fn expect(&mut self, words: &[&str], line: &str) -> Result<(), String> {
+		match words {
+			["state", state] => {
+				let state_id = self.states.pop_front().ok_or("No state entered")?;
+
+				if format!("{:?}", state_id) != *state {
+					return Err(format!("Entered state {:?}", state_id));
+				}
+			}
+			["display", kind, ..] => {
+				let msg = self.display.pop_front().ok_or("Nothing sent to the display")?;
+
+				let matches = match (*kind, &msg) {
+					("time", AlphanumMessage::Time) => true,
+					("countdown", AlphanumMessage::Countdown(end)) => end.time().format("%H:%M:%S").to_string() == rest(line, 3),
+					("elapsed", AlphanumMessage::Elapsed(start)) => start.time().format("%H:%M:%S").to_string() == rest(line, 3),
+					("empty", AlphanumMessage::Empty) => true,
+					("static", AlphanumMessage::Static(text)) => *text == rest(line, 3),
+					("glyphs", AlphanumMessage::Glyphs(glyphs)) => glyph_text(glyphs) == rest(line, 3),
+					("marked", AlphanumMessage::Marked(text, marker)) => format!("{} {}", text, marker) == rest(line, 3),
+					("loop", AlphanumMessage::Loop(text)) => *text == rest(line, 3),
+					("blink", AlphanumMessage::Blink(rate)) => format!("{:?}", rate) == rest(line, 3),
+					_ => false,
+				};
+
+				if !matches {
+					return Err(format!("Display was sent {:?}", msg));
+				}
+			}
+			["player", kind, ..] => {
+				let msg = self.player.pop_front().ok_or("Nothing sent to the player")?;
+
+				let matches = match (*kind, &msg) {
+					("play", PlayerMessage::Play(path)) => song_name(path) == rest(line, 3),
+					("loop", PlayerMessage::Loop(path)) => song_name(path) == rest(line, 3),
+					("gentle", PlayerMessage::Gentle(path)) => song_name(path) == rest(line, 3),
+					("stop", PlayerMessage::Stop) => true,
+					("speed", PlayerMessage::Speed(speed)) => Ok(*speed) == rest(line, 3).parse::<f64>(),
+					_ => false,
+				};
+
+				if !matches {
+					return Err(format!("Player was sent {:?}", msg));
+				}
+			}
+			["clock", time] => {
+				let clock_time = self.clock.clock_time();
+
+				if clock_time != parse_time(time)? {
+					return Err(format!("Clock is {:?}", clock_time));
+				}
+			}
+			["date", date] => {
+				let clock_date = self.clock.date();
+
+				if date.parse() != Ok(clock_date) {
+					return Err(format!("Date is {}", clock_date));
+				}
+			}
+			["alarms", count] => {
+				let len = ALARMS.read().len();
+
+				if count.parse() != Ok(len) {
+					return Err(format!("There are {} alarms", len));
+				}
+			}
+			["alarm", n, "repeat", ..] => {
+				let alarm = self.alarm(n)?;
+
+				if alarm.repeat.to_string() != rest(line, 4) {
+					return Err(format!("Alarm repeats {}", alarm.repeat));
+				}
+			}
+			["alarm", n, "wake", ..] => {
+				let alarm = self.alarm(n)?;
+
+				if alarm.wake_name() != rest(line, 4) {
+					return Err(format!("Alarm wakes gently {}", alarm.wake_name()));
+				}
+			}
+			["alarm", n, "dismiss", ..] => {
+				let alarm = self.alarm(n)?;
+
+				if alarm.challenge_name() != rest(line, 4) {
+					return Err(format!("Alarm is dismissed with {}", alarm.challenge_name()));
+				}
+			}
+			["alarm", n, field, ..] => {
+				let alarm = self.alarm(n)?;
+
+				let matches = match *field {
+					"time" => Some(alarm.time) == words.get(3).map(|t| parse_time(t)).transpose()?,
+					"song" => alarm.song.as_deref().map(song_name) == Some(rest(line, 4)),
+					"on" => alarm.enabled,
+					"off" => !alarm.enabled,
+					_ => return Err("Unknown expectation".to_owned()),
+				};
+
+				if !matches {
+					return Err(format!("Alarm is {:?}", alarm));
+				}
+			}
+			_ => return Err("Unknown expectation".to_owned()),
+		}
+
+		Ok(())
+	}
+}
+
+impl Drop for Scenario {
+	fn drop(&mut self) {
+		if let Some(config) = self.device_config.take() {
+			*CONFIG.write() = config;
+		}
+	}
+}
+
+/// Text after the first `words` words of a line, without surrounding quotes.
+fn rest(line: &str, words: usize) -> String {
+	let rest = line.splitn(words + 1, char::is_whitespace)
+		.nth(words)
+		.unwrap_or("")
+		.trim();
+
+	rest.strip_prefix('"')
+		.and_then(|r| r.strip_suffix('"'))
+		.unwrap_or(rest)
+		.to_owned()
+}
+
+fn parse_time(time: &str) -> Result<ClockTime, String> {
+	let (hours, minutes) = time.split_once(':')
+		.and_then(|(h, m)| Some((h.parse::<u16>().ok()?, m.parse::<u16>().ok()?)))
+		.ok_or_else(|| format!("Expected time like 07:30, found {:?}", time))?;
+
+	Ok(ClockTime::new(hours * 60 + minutes))
+}
@@ -4,6 +4,7 @@ pub trait Selector<T> {
 	fn incr(&mut self) -> &T;
 	fn decr(&mut self) -> &T;
 	fn curr(&self) -> &T;
+	#[allow(dead_code)]
 	fn reset(&mut self);
 	fn len(&self) -> usize;
 }
@@ -65,7 +66,7 @@ impl <T> BinarySelector<T> {
 	fn curr_i(&self) -> usize {
 		match self.bound {
 			Bound::Single(i) => i,
-			Bound::Range(i, j) => i + (j - i + 1) / 2,
+			Bound::Range(i, j) => i + (j - i).div_ceil(2),
 		}
 	}
 }
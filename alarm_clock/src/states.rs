@@ -1,9 +1,15 @@
 use std::fmt;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::sync::mpsc::Sender;
-use std::time::{ Duration, Instant };
 
-use crate::{ TIME_ZERO, ALARM_TIME, ALARM_SONG, ClockTime };
+use chrono::{ Datelike, NaiveDate, NaiveDateTime };
+
+use crate::{ ALARMS, CONFIG, NAP, RINGING, SLEEP, TIMER, ClockTime };
+use crate::alarm::{ Alarm, Challenge, Repeat, SleepTimer, Timer, WEEKDAYS, day_name };
+use crate::clock::Clock;
+use crate::threads::player::song_name;
+use crate::time_format::{ duration_chars, pm_dot_glyphs, AmPmMarker };
 
 use crate::selector::{ BinarySelector, LinearSelector, Selector };
 
@@ -13,15 +19,21 @@ use crate::message::{
 	EventMessage,
 	ButtonEvent,
 	SongEvent,
+	AlarmEvent,
 };
 
 fn send_time(alphanum_sender: &mut Sender<AlphanumMessage>, time: ClockTime) {
-	alphanum_sender.send(AlphanumMessage::Static(time.as_chars()))
-		.expect("Unable to send selected time to alphanum");
-}
+	let config = CONFIG.read();
+
+	let msg = match (config.twelve_hour, config.am_pm_marker) {
+		(false, _) => AlphanumMessage::Static(time.as_chars().iter().collect()),
+		(true, AmPmMarker::Text) => AlphanumMessage::Marked(time.as_chars().iter().collect(), time.am_pm_chars().iter().collect()),
+		(true, AmPmMarker::Dot) => AlphanumMessage::Glyphs(pm_dot_glyphs(time.as_chars(), time.is_pm()).to_vec()),
+	};
+	drop(config);
 
-fn set_time(time: ClockTime) {
-	*TIME_ZERO.write() = Instant::now() - Duration::from(time)
+	alphanum_sender.send(msg)
+		.expect("Unable to send selected time to alphanum");
 }
 
 
@@ -40,6 +52,11 @@ pub trait State {
 				SongEvent::Start(name) => self.song_start(name),
 				SongEvent::End(name) => self.song_end(name),
 			}
+			EventMessage::Alarm(alarm) => match alarm {
+				AlarmEvent::Escalate(name) => self.alarm_escalate(name),
+				AlarmEvent::Stop => self.alarm_stop(),
+			}
+			EventMessage::Tick => self.tick(),
 		}
 	}
 
@@ -58,15 +75,38 @@ pub trait State {
 	fn song_end(&mut self, _name: String) -> Option<StateId> {
 		None
 	}
+
+	fn alarm_escalate(&mut self, _name: String) -> Option<StateId> {
+		None
+	}
+
+	fn alarm_stop(&mut self) -> Option<StateId> {
+		None
+	}
+
+	fn tick(&mut self) -> Option<StateId> {
+		None
+	}
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum StateId {
 	Clock,
 	ModeSelect,
 	ClockSet,
-	AlarmTime,
-	AlarmSong,
+	DateSet,
+	Alarms,
+	/// Menu for the alarm at this index of `ALARMS`.
+	AlarmEdit(usize),
+	AlarmTime(usize),
+	AlarmSong(usize),
+	AlarmRepeat(usize),
+	AlarmDays(usize),
+	/// Task to complete before the ringing alarm is dismissed.
+	Challenge,
+	Nap,
+	Timer,
+	Stopwatch,
 	Play,
 	Bad,
 }
@@ -79,8 +119,17 @@ impl StateId {
 			Clock => "Clock",
 			ModeSelect => "Mode Select",
 			ClockSet => "Clock Set",
-			AlarmTime => "Alarm Time",
-			AlarmSong => "Alarm Song",
+			DateSet => "Date Set",
+			Alarms => "Alarms",
+			AlarmEdit(_) => "Alarm Edit",
+			AlarmTime(_) => "Alarm Time",
+			AlarmSong(_) => "Alarm Song",
+			AlarmRepeat(_) => "Alarm Repeat",
+			AlarmDays(_) => "Alarm Days",
+			Challenge => "Challenge",
+			Nap => "Nap",
+			Timer => "Timer",
+			Stopwatch => "Stopwatch",
 			Play => "Play",
 			Bad => "Bad",
 		}
@@ -93,37 +142,364 @@ impl fmt::Display for StateId {
 	}
 }
 
+/// Runs the current state, entering the next state whenever one finishes.
+pub struct StateMachine {
+	state: Option<Box<dyn State>>,
+	alphanum_sender: Sender<AlphanumMessage>,
+	player_sender: Sender<PlayerMessage>,
+	clock: Arc<dyn Clock>,
+	midi_files: Vec<PathBuf>,
+}
+
+impl StateMachine {
+	/// Create the state machine, starting in the clock state.
+	pub fn new(
+		alphanum_sender: Sender<AlphanumMessage>,
+		player_sender: Sender<PlayerMessage>,
+		clock: Arc<dyn Clock>,
+		midi_files: Vec<PathBuf>,
+	) -> Self {
+		let mut state_machine = Self {
+			state: None,
+			alphanum_sender,
+			player_sender,
+			clock,
+			midi_files,
+		};
+
+		state_machine.enter(StateId::Clock);
+
+		state_machine
+	}
+
+	/// Pass an event to the current state, returning the state entered if it finished.
+	///
+	/// Once the bad state is entered, events are ignored.
+	pub fn event(&mut self, event: EventMessage) -> Option<StateId> {
+		let next_state = self.state.as_mut()?.event(event)?;
+
+		self.transition(next_state);
+
+		Some(next_state)
+	}
+
+	/// Finish the current state and enter the bad state, unless already there.
+	pub fn stop(&mut self) {
+		if self.state.is_some() {
+			self.transition(StateId::Bad);
+		}
+	}
+
+	fn transition(&mut self, next_state: StateId) {
+		if let Some(state) = &mut self.state {
+			state.finish();
+		}
+
+		self.enter(next_state);
+	}
+
+	fn enter(&mut self, state_id: StateId) {
+		println!("Entering state {:?}", state_id);
+
+		let alphanum_sender = self.alphanum_sender.clone();
+		let player_sender = self.player_sender.clone();
+		let midi_files = self.midi_files.clone();
+
+		self.state = match state_id {
+			StateId::Clock => Some(Box::new(StateClock::new(alphanum_sender, player_sender, self.clock.clone()))),
+			StateId::ModeSelect => Some(Box::new(StateModeSelect::new(alphanum_sender))),
+			StateId::ClockSet => Some(Box::new(StateClockSet::new(alphanum_sender, self.clock.clone()))),
+			StateId::DateSet => Some(Box::new(StateDateSet::new(alphanum_sender, self.clock.clone()))),
+			StateId::Alarms => Some(Box::new(StateAlarms::new(alphanum_sender, midi_files))),
+			StateId::AlarmEdit(i) => Some(Box::new(StateAlarmEdit::new(alphanum_sender, i))),
+			StateId::AlarmTime(i) => Some(Box::new(StateAlarmTimeSet::new(alphanum_sender, i))),
+			StateId::AlarmSong(i) => Some(Box::new(StateAlarmSongSet::new(alphanum_sender, player_sender, midi_files, i))),
+			StateId::AlarmRepeat(i) => Some(Box::new(StateAlarmRepeat::new(alphanum_sender, i))),
+			StateId::AlarmDays(i) => Some(Box::new(StateAlarmDays::new(alphanum_sender, i))),
+			StateId::Challenge => Some(Box::new(StateChallenge::new(alphanum_sender, player_sender, self.clock.clone()))),
+			StateId::Nap => Some(Box::new(StateNap::new(alphanum_sender, self.clock.clone()))),
+			StateId::Timer => Some(Box::new(StateTimer::new(alphanum_sender, player_sender, self.clock.clone()))),
+			StateId::Stopwatch => Some(Box::new(StateStopwatch::new(alphanum_sender, self.clock.clone()))),
+			StateId::Play => Some(Box::new(StatePlay::new(alphanum_sender, player_sender, self.clock.clone(), midi_files))),
+			StateId::Bad => None,
+		};
+
+		if let Some(state) = &mut self.state {
+			state.init();
+		}
+	}
+}
+
 pub struct StateClock {
 	alphanum_sender: Sender<AlphanumMessage>,
 	player_sender: Sender<PlayerMessage>,
+	clock: Arc<dyn Clock>,
+	showing_snooze: bool,
 }
 
 impl StateClock {
 	pub fn new(
 		alphanum_sender: Sender<AlphanumMessage>,
 		player_sender: Sender<PlayerMessage>,
+		clock: Arc<dyn Clock>,
 	) -> Self {
-		Self { alphanum_sender, player_sender }
+		Self { alphanum_sender, player_sender, clock, showing_snooze: false }
+	}
+
+	/// Show when a snoozed alarm goes off again, or the time if no alarm is snoozed.
+	fn show_snooze(&mut self) {
+		let snoozed_until = RINGING.read().as_ref().and_then(|ringing| ringing.snoozed_until);
+
+		let msg = match snoozed_until {
+			Some(until) => {
+				let until = ClockTime::from(self.clock.steady_to_local(until).time()).text();
+
+				AlphanumMessage::Loop(format!("Snooze {}", until))
+			}
+			None => AlphanumMessage::Time,
+		};
+
+		self.showing_snooze = snoozed_until.is_some();
+		self.alphanum_sender.send(msg).unwrap();
+	}
+
+	fn stop_alarm(&self) {
+		self.player_sender.send(PlayerMessage::Stop)
+			.expect("Unable to stop alarm");
+	}
+
+	/// Stop a ringing alarm until the snooze runs out. Once the alarm has been snoozed as many
+	/// times as allowed it keeps ringing until dismissed.
+	fn snooze(&mut self) {
+		let snoozed = match &mut *RINGING.write() {
+			Some(ringing) if ringing.snoozed_until.is_none() => {
+				let config = CONFIG.read();
+
+				if ringing.snoozes < config.max_snoozes {
+					ringing.snoozes += 1;
+					ringing.snoozed_until = Some(self.clock.steady_now() + chrono::Duration::minutes(config.snooze_minutes.into()));
+				}
+
+				ringing.snoozed_until.is_some()
+			}
+			Some(_) => false,
+			None => return self.stop_alarm(),
+		};
+
+		if snoozed {
+			self.stop_alarm();
+			self.show_snooze();
+		}
+	}
+
+	/// Stop a ringing or snoozed alarm for good, unless there's a challenge to complete first.
+	fn dismiss(&mut self) -> Option<StateId> {
+		if RINGING.read().as_ref().is_some_and(|ringing| ringing.challenge != Challenge::None) {
+			return Some(StateId::Challenge);
+		}
+
+		RINGING.write().take();
+
+		self.stop_alarm();
+
+		if self.showing_snooze {
+			self.show_snooze();
+		}
+
+		None
 	}
 }
 
 impl State for StateClock {
 	fn init(&mut self) {
-		self.alphanum_sender.send(AlphanumMessage::Time).unwrap();
+		self.show_snooze();
 	}
 
 	fn button_press(&mut self, button_id: u8) -> Option<StateId> {
 		match button_id {
-			0 | 1 => {
-				self.player_sender.send(PlayerMessage::Stop)
-					.expect("Unable to stop alarm");
+			0 => {
+				self.snooze();
 
 				None
 			}
+			1 => self.dismiss(),
 			2 => Some(StateId::ModeSelect),
 			_ => None,
 		}
 	}
+
+	fn song_start(&mut self, _name: String) -> Option<StateId> {
+		// a snoozed alarm has gone off again
+		if self.showing_snooze {
+			self.show_snooze();
+		}
+
+		None
+	}
+}
+
+/// Buttons used in sequence challenges, and how they are shown.
+const SEQUENCE_BUTTONS: [(u8, char); 3] = [(0, 'D'), (1, 'U'), (2, 'M')];
+
+/// Seconds without a button press before a challenge goes back to the clock, leaving the alarm
+/// ringing.
+const CHALLENGE_IDLE_SECONDS: i64 = 60;
+
+pub struct StateChallenge {
+	alphanum_sender: Sender<AlphanumMessage>,
+	player_sender: Sender<PlayerMessage>,
+	clock: Arc<dyn Clock>,
+	challenge: Challenge,
+	/// Indices into `SEQUENCE_BUTTONS` to press.
+	sequence: [usize; 4],
+	entered: usize,
+	attempts: u64,
+	held_since: Option<NaiveDateTime>,
+	last_press: NaiveDateTime,
+}
+
+impl StateChallenge {
+	pub fn new(
+		alphanum_sender: Sender<AlphanumMessage>,
+		player_sender: Sender<PlayerMessage>,
+		clock: Arc<dyn Clock>,
+	) -> Self {
+		let challenge = RINGING.read().as_ref().map_or(Challenge::None, |ringing| ringing.challenge);
+		let last_press = clock.now();
+
+		Self {
+			alphanum_sender,
+			player_sender,
+			clock,
+			challenge,
+			sequence: [0; 4],
+			entered: 0,
+			attempts: 0,
+			held_since: None,
+			last_press,
+		}
+	}
+
+	/// Pick a new sequence, mixed from the time so a virtual clock gives the same one every run.
+	fn new_sequence(&mut self) {
+		let mut seed = (self.clock.now().timestamp_nanos() as u64) ^ self.attempts;
+
+		for button in &mut self.sequence {
+			// splitmix64
+			seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+			let mut z = seed;
+			z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+			z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+			z ^= z >> 31;
+
+			*button = (z % SEQUENCE_BUTTONS.len() as u64) as usize;
+		}
+
+		self.attempts += 1;
+		self.entered = 0;
+		self.send_sequence();
+	}
+
+	/// Show the rest of the sequence, with buttons already pressed shown as dashes.
+	fn send_sequence(&mut self) {
+		let mut chars = ['-'; 4];
+
+		for (i, &button) in self.sequence.iter().enumerate().skip(self.entered) {
+			chars[i] = SEQUENCE_BUTTONS[button].1;
+		}
+
+		self.alphanum_sender.send(AlphanumMessage::Static(chars.iter().collect())).unwrap();
+	}
+
+	fn dismiss(&mut self) -> Option<StateId> {
+		RINGING.write().take();
+
+		self.player_sender.send(PlayerMessage::Stop)
+			.expect("Unable to stop alarm");
+
+		Some(StateId::Clock)
+	}
+}
+
+impl State for StateChallenge {
+	fn init(&mut self) {
+		match self.challenge {
+			Challenge::None => (),
+			Challenge::Sequence => self.new_sequence(),
+			Challenge::Hold => self.alphanum_sender.send(AlphanumMessage::Loop("Hold mode".to_string())).unwrap(),
+		}
+	}
+
+	fn button_press(&mut self, button_id: u8) -> Option<StateId> {
+		self.last_press = self.clock.now();
+
+		match self.challenge {
+			Challenge::None => self.dismiss(),
+			Challenge::Sequence => {
+				if SEQUENCE_BUTTONS[self.sequence[self.entered]].0 != button_id {
+					self.new_sequence();
+					return None;
+				}
+
+				self.entered += 1;
+
+				if self.entered == self.sequence.len() {
+					self.dismiss()
+				} else {
+					self.send_sequence();
+					None
+				}
+			}
+			Challenge::Hold => {
+				if button_id == 2 {
+					self.held_since = Some(self.clock.now());
+				}
+
+				None
+			}
+		}
+	}
+
+	fn button_release(&mut self, button_id: u8) -> Option<StateId> {
+		if self.challenge != Challenge::Hold || button_id != 2 {
+			return None;
+		}
+
+		let hold = chrono::Duration::seconds(CONFIG.read().hold_seconds.into());
+
+		match self.held_since.take() {
+			Some(since) if self.clock.now() - since >= hold => self.dismiss(),
+			_ => {
+				self.alphanum_sender.send(AlphanumMessage::Loop("Hold longer".to_string())).unwrap();
+				None
+			}
+		}
+	}
+
+	fn alarm_stop(&mut self) -> Option<StateId> {
+		Some(StateId::Clock)
+	}
+
+	/// Dismiss the alarm once mode has been held long enough, without waiting for it to be let go,
+	/// or give up on the challenge if nothing has been pressed for a while. Releases are never lost
+	/// to debouncing, so mode is only taken as held until its release arrives.
+	fn tick(&mut self) -> Option<StateId> {
+		let now = self.clock.now();
+
+		match self.held_since {
+			Some(since) => {
+				let hold = chrono::Duration::seconds(CONFIG.read().hold_seconds.into());
+
+				if now - since >= hold {
+					self.dismiss()
+				} else {
+					None
+				}
+			}
+			None if now - self.last_press >= chrono::Duration::seconds(CHALLENGE_IDLE_SECONDS) => Some(StateId::Clock),
+			None => None,
+		}
+	}
 }
 
 pub struct StateModeSelect {
@@ -138,8 +514,11 @@ impl StateModeSelect {
 			mode_selector: LinearSelector::new(vec![
 				StateId::Clock,
 				StateId::ClockSet,
-				StateId::AlarmTime,
-				StateId::AlarmSong,
+				StateId::DateSet,
+				StateId::Alarms,
+				StateId::Nap,
+				StateId::Timer,
+				StateId::Stopwatch,
 				StateId::Play,
 			]),
 		}
@@ -172,16 +551,18 @@ impl State for StateModeSelect {
 
 pub struct StateClockSet {
 	alphanum_sender: Sender<AlphanumMessage>,
+	clock: Arc<dyn Clock>,
 	time_selector: BinarySelector<ClockTime>,
 }
 
 impl StateClockSet {
-	pub fn new(alphanum_sender: Sender<AlphanumMessage>) -> Self {
+	pub fn new(alphanum_sender: Sender<AlphanumMessage>, clock: Arc<dyn Clock>) -> Self {
 		Self {
 			alphanum_sender,
+			clock,
 			time_selector: BinarySelector::new(
 				(0..24*60)
-					.map(|i| ClockTime::new(i))
+					.map(ClockTime::new)
 					.collect::<Vec<_>>()
 			),
 		}
@@ -207,7 +588,7 @@ impl State for StateClockSet {
 				None
 			}
 			2 => {
-				set_time(*self.time_selector.curr());
+				self.clock.set_time(*self.time_selector.curr());
 
 				Some(StateId::Clock)
 			}
@@ -216,18 +597,458 @@ impl State for StateClockSet {
 	}
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DatePhase {
+	Year,
+	Month,
+	Day,
+}
+
+/// Set the date in years, then months, then days, keeping the time of day.
+pub struct StateDateSet {
+	alphanum_sender: Sender<AlphanumMessage>,
+	clock: Arc<dyn Clock>,
+	phase: DatePhase,
+	date: NaiveDate,
+}
+
+impl StateDateSet {
+	pub fn new(alphanum_sender: Sender<AlphanumMessage>, clock: Arc<dyn Clock>) -> Self {
+		let date = clock.date();
+
+		Self {
+			alphanum_sender,
+			clock,
+			phase: DatePhase::Year,
+			date,
+		}
+	}
+
+	fn send_phase(&mut self) {
+		let text = match self.phase {
+			DatePhase::Year => self.date.format("%Y").to_string(),
+			DatePhase::Month => self.date.format("%b").to_string(),
+			DatePhase::Day => format!("{}{:02}", &day_name(self.date.weekday())[..2], self.date.day()),
+		};
+
+		self.alphanum_sender.send(AlphanumMessage::Static(text)).unwrap();
+	}
+
+	/// Move the date along one of whatever is being set, wrapping months and days round without
+	/// changing the rest of the date.
+	fn step(&mut self, forward: bool) {
+		let (mut year, mut month, mut day) = (self.date.year(), self.date.month(), self.date.day());
+
+		match (self.phase, forward) {
+			(DatePhase::Year, true) => year = (year + 1).min(MAX_YEAR),
+			(DatePhase::Year, false) => year = (year - 1).max(MIN_YEAR),
+			(DatePhase::Month, true) => month = month % 12 + 1,
+			(DatePhase::Month, false) => month = (month + 10) % 12 + 1,
+			(DatePhase::Day, true) => day = day % days_in_month(year, month) + 1,
+			(DatePhase::Day, false) => day = (day + days_in_month(year, month) - 2) % days_in_month(year, month) + 1,
+		}
+
+		// the 31st becomes the last day of shorter months
+		self.date = NaiveDate::from_ymd(year, month, day.min(days_in_month(year, month)));
+	}
+}
+
+/// Years the date can be set between.
+const MIN_YEAR: i32 = 2000;
+const MAX_YEAR: i32 = 2099;
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+	let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+
+	NaiveDate::from_ymd(next_year, next_month, 1).pred().day()
+}
+
+impl State for StateDateSet {
+	fn init(&mut self) {
+		self.send_phase();
+	}
+
+	fn button_press(&mut self, button_id: u8) -> Option<StateId> {
+		match (self.phase, button_id) {
+			(_, 0) => self.step(true),
+			(_, 1) => self.step(false),
+			(DatePhase::Year, 2) => self.phase = DatePhase::Month,
+			(DatePhase::Month, 2) => self.phase = DatePhase::Day,
+			(DatePhase::Day, 2) => {
+				self.clock.set(self.date.and_time(self.clock.now().time()));
+
+				return Some(StateId::Clock);
+			}
+			_ => return None,
+		}
+
+		self.send_phase();
+
+		None
+	}
+}
+
+pub struct StateAlarms {
+	alphanum_sender: Sender<AlphanumMessage>,
+	midi_files: Vec<PathBuf>,
+	item_selector: LinearSelector<AlarmsItem>,
+}
+
+#[derive(Clone, Copy)]
+enum AlarmsItem {
+	Alarm(usize),
+	New,
+	Back,
+}
+
+impl StateAlarms {
+	pub fn new(alphanum_sender: Sender<AlphanumMessage>, midi_files: Vec<PathBuf>) -> Self {
+		let items = (0..ALARMS.read().len())
+			.map(AlarmsItem::Alarm)
+			.chain(vec![AlarmsItem::New, AlarmsItem::Back])
+			.collect();
+
+		Self {
+			alphanum_sender,
+			midi_files,
+			item_selector: LinearSelector::new(items),
+		}
+	}
+
+	fn send_item(&mut self) {
+		let text = match *self.item_selector.curr() {
+			AlarmsItem::Alarm(i) => match ALARMS.read().get(i) {
+				Some(alarm) => format!("Alarm {} {}", i + 1, alarm),
+				None => "Missing".to_owned(),
+			}
+			AlarmsItem::New => "New Alarm".to_owned(),
+			AlarmsItem::Back => "Back".to_owned(),
+		};
+
+		self.alphanum_sender.send(AlphanumMessage::Loop(text)).unwrap();
+	}
+}
+
+impl State for StateAlarms {
+	fn init(&mut self) {
+		self.send_item();
+	}
+
+	fn button_press(&mut self, button_id: u8) -> Option<StateId> {
+		match button_id {
+			0 | 1 => {
+				if button_id == 0 {
+					self.item_selector.incr();
+				} else {
+					self.item_selector.decr();
+				}
+
+				self.send_item();
+
+				None
+			}
+			2 => match *self.item_selector.curr() {
+				AlarmsItem::Alarm(i) => Some(StateId::AlarmEdit(i)),
+				AlarmsItem::New => {
+					let mut alarms = ALARMS.write();
+
+					alarms.push(Alarm::new(ClockTime::new(12 * 60), self.midi_files.first().cloned()));
+
+					Some(StateId::AlarmTime(alarms.len() - 1))
+				}
+				AlarmsItem::Back => Some(StateId::Clock),
+			}
+			_ => None,
+		}
+	}
+}
+
+pub struct StateAlarmEdit {
+	alphanum_sender: Sender<AlphanumMessage>,
+	alarm_index: usize,
+	action_selector: LinearSelector<AlarmAction>,
+}
+
+#[derive(Clone, Copy)]
+enum AlarmAction {
+	Time,
+	Song,
+	Repeat,
+	Challenge,
+	Wake,
+	Toggle,
+	Delete,
+	Back,
+}
+
+/// Choices for how many minutes before an alarm to start waking up gently, cycled through in order.
+const WAKE_MINUTES: [u32; 6] = [0, 5, 10, 15, 20, 30];
+
+impl StateAlarmEdit {
+	pub fn new(alphanum_sender: Sender<AlphanumMessage>, alarm_index: usize) -> Self {
+		Self {
+			alphanum_sender,
+			alarm_index,
+			action_selector: LinearSelector::new(vec![
+				AlarmAction::Time,
+				AlarmAction::Song,
+				AlarmAction::Repeat,
+				AlarmAction::Challenge,
+				AlarmAction::Wake,
+				AlarmAction::Toggle,
+				AlarmAction::Delete,
+				AlarmAction::Back,
+			]),
+		}
+	}
+
+	fn send_action(&mut self) {
+		let text = match ALARMS.read().get(self.alarm_index) {
+			Some(alarm) => match self.action_selector.curr() {
+				AlarmAction::Time => format!("Time {}", alarm.time.text()),
+				AlarmAction::Song => format!("Song {}", alarm.song.as_deref().map_or("None".to_owned(), song_name)),
+				AlarmAction::Repeat => format!("Repeat {}", alarm.repeat),
+				AlarmAction::Challenge => format!("Dismiss {}", alarm.challenge_name()),
+				AlarmAction::Wake => format!("Wake {}", alarm.wake_name()),
+				AlarmAction::Toggle => alarm.on_off().to_owned(),
+				AlarmAction::Delete => "Delete".to_owned(),
+				AlarmAction::Back => "Back".to_owned(),
+			}
+			None => "Missing".to_owned(),
+		};
+
+		self.alphanum_sender.send(AlphanumMessage::Loop(text)).unwrap();
+	}
+}
+
+impl State for StateAlarmEdit {
+	fn init(&mut self) {
+		self.send_action();
+	}
+
+	fn button_press(&mut self, button_id: u8) -> Option<StateId> {
+		match button_id {
+			0 | 1 => {
+				if button_id == 0 {
+					self.action_selector.incr();
+				} else {
+					self.action_selector.decr();
+				}
+
+				self.send_action();
+
+				None
+			}
+			2 => match self.action_selector.curr() {
+				AlarmAction::Time => Some(StateId::AlarmTime(self.alarm_index)),
+				AlarmAction::Song => Some(StateId::AlarmSong(self.alarm_index)),
+				AlarmAction::Repeat => Some(StateId::AlarmRepeat(self.alarm_index)),
+				AlarmAction::Challenge => {
+					if let Some(alarm) = ALARMS.write().get_mut(self.alarm_index) {
+						alarm.challenge = match alarm.challenge {
+							None => Some(Challenge::None),
+							Some(Challenge::None) => Some(Challenge::Sequence),
+							Some(Challenge::Sequence) => Some(Challenge::Hold),
+							Some(Challenge::Hold) => None,
+						};
+					}
+
+					self.send_action();
+
+					None
+				}
+				AlarmAction::Wake => {
+					if let Some(alarm) = ALARMS.write().get_mut(self.alarm_index) {
+						let next = WAKE_MINUTES.iter().position(|&minutes| minutes == alarm.wake_minutes)
+							.map_or(0, |i| (i + 1) % WAKE_MINUTES.len());
+
+						alarm.wake_minutes = WAKE_MINUTES[next];
+					}
+
+					self.send_action();
+
+					None
+				}
+				AlarmAction::Toggle => {
+					if let Some(alarm) = ALARMS.write().get_mut(self.alarm_index) {
+						alarm.enabled = !alarm.enabled;
+					}
+
+					self.send_action();
+
+					None
+				}
+				AlarmAction::Delete => {
+					let mut alarms = ALARMS.write();
+
+					if self.alarm_index < alarms.len() {
+						alarms.remove(self.alarm_index);
+					}
+
+					Some(StateId::Alarms)
+				}
+				AlarmAction::Back => Some(StateId::Alarms),
+			}
+			_ => None,
+		}
+	}
+}
+
+pub struct StateAlarmRepeat {
+	alphanum_sender: Sender<AlphanumMessage>,
+	alarm_index: usize,
+	repeat_selector: LinearSelector<Repeat>,
+}
+
+impl StateAlarmRepeat {
+	pub fn new(alphanum_sender: Sender<AlphanumMessage>, alarm_index: usize) -> Self {
+		let days = match ALARMS.read().get(alarm_index).map(|alarm| alarm.repeat) {
+			Some(Repeat::Days(days)) => days,
+			_ => 0,
+		};
+
+		Self {
+			alphanum_sender,
+			alarm_index,
+			repeat_selector: LinearSelector::new(vec![
+				Repeat::Daily,
+				Repeat::Weekdays,
+				Repeat::Weekends,
+				Repeat::Days(days),
+				Repeat::Once,
+			]),
+		}
+	}
+
+	fn send_repeat(&mut self) {
+		let text = match self.repeat_selector.curr() {
+			Repeat::Days(_) => "Custom".to_owned(),
+			repeat => repeat.to_string(),
+		};
+
+		self.alphanum_sender.send(AlphanumMessage::Loop(text)).unwrap();
+	}
+}
+
+impl State for StateAlarmRepeat {
+	fn init(&mut self) {
+		self.send_repeat();
+	}
+
+	fn button_press(&mut self, button_id: u8) -> Option<StateId> {
+		match button_id {
+			0 | 1 => {
+				if button_id == 0 {
+					self.repeat_selector.incr();
+				} else {
+					self.repeat_selector.decr();
+				}
+
+				self.send_repeat();
+
+				None
+			}
+			2 => {
+				let repeat = *self.repeat_selector.curr();
+
+				if let Some(alarm) = ALARMS.write().get_mut(self.alarm_index) {
+					alarm.repeat = repeat;
+				}
+
+				match repeat {
+					Repeat::Days(_) => Some(StateId::AlarmDays(self.alarm_index)),
+					_ => Some(StateId::AlarmEdit(self.alarm_index)),
+				}
+			}
+			_ => None,
+		}
+	}
+}
+
+/// Choose the days for an alarm with custom repeat days.
+pub struct StateAlarmDays {
+	alphanum_sender: Sender<AlphanumMessage>,
+	alarm_index: usize,
+	day_selector: LinearSelector<Option<chrono::Weekday>>,
+}
+
+impl StateAlarmDays {
+	pub fn new(alphanum_sender: Sender<AlphanumMessage>, alarm_index: usize) -> Self {
+		Self {
+			alphanum_sender,
+			alarm_index,
+			day_selector: LinearSelector::new(
+				WEEKDAYS.iter().copied().map(Some).chain(vec![None]).collect()
+			),
+		}
+	}
+
+	fn send_day(&mut self) {
+		let text = match (*self.day_selector.curr(), ALARMS.read().get(self.alarm_index)) {
+			(Some(day), Some(alarm)) => {
+				let on_off = if alarm.repeat.includes(day) { "On" } else { "Off" };
+
+				format!("{} {}", day_name(day), on_off)
+			}
+			(None, _) => "Done".to_owned(),
+			(_, None) => "Missing".to_owned(),
+		};
+
+		self.alphanum_sender.send(AlphanumMessage::Loop(text)).unwrap();
+	}
+}
+
+impl State for StateAlarmDays {
+	fn init(&mut self) {
+		self.send_day();
+	}
+
+	fn button_press(&mut self, button_id: u8) -> Option<StateId> {
+		match button_id {
+			0 | 1 => {
+				if button_id == 0 {
+					self.day_selector.incr();
+				} else {
+					self.day_selector.decr();
+				}
+
+				self.send_day();
+
+				None
+			}
+			2 => match *self.day_selector.curr() {
+				Some(day) => {
+					if let Some(alarm) = ALARMS.write().get_mut(self.alarm_index) {
+						if let Repeat::Days(days) = alarm.repeat {
+							alarm.repeat = Repeat::Days(days ^ Repeat::day_bit(day));
+						}
+					}
+
+					self.send_day();
+
+					None
+				}
+				None => Some(StateId::AlarmEdit(self.alarm_index)),
+			}
+			_ => None,
+		}
+	}
+}
+
 pub struct StateAlarmTimeSet {
 	alphanum_sender: Sender<AlphanumMessage>,
+	alarm_index: usize,
 	time_selector: BinarySelector<ClockTime>,
 }
 
 impl StateAlarmTimeSet {
-	pub fn new(alphanum_sender: Sender<AlphanumMessage>) -> Self {
+	pub fn new(alphanum_sender: Sender<AlphanumMessage>, alarm_index: usize) -> Self {
 		Self {
 			alphanum_sender,
+			alarm_index,
 			time_selector: BinarySelector::new(
 				(0..24*60)
-					.map(|i| ClockTime::new(i))
+					.map(ClockTime::new)
 					.collect::<Vec<_>>()
 			),
 		}
@@ -253,9 +1074,11 @@ impl State for StateAlarmTimeSet {
 				None
 			}
 			2 => {
-				*ALARM_TIME.write() = Some(*self.time_selector.curr());
+				if let Some(alarm) = ALARMS.write().get_mut(self.alarm_index) {
+					alarm.time = *self.time_selector.curr();
+				}
 
-				Some(StateId::Clock)
+				Some(StateId::AlarmEdit(self.alarm_index))
 			}
 			_ => None,
 		}
@@ -265,6 +1088,7 @@ impl State for StateAlarmTimeSet {
 pub struct StateAlarmSongSet {
 	alphanum_sender: Sender<AlphanumMessage>,
 	player_sender: Sender<PlayerMessage>,
+	alarm_index: usize,
 	midi_selector: LinearSelector<PathBuf>,
 }
 
@@ -273,10 +1097,12 @@ impl StateAlarmSongSet {
 		alphanum_sender: Sender<AlphanumMessage>,
 		player_sender: Sender<PlayerMessage>,
 		midi_files: Vec<PathBuf>,
+		alarm_index: usize,
 	) -> Self {
 		Self {
 			alphanum_sender,
 			player_sender,
+			alarm_index,
 			midi_selector: LinearSelector::new(midi_files.clone()),
 		}
 	}
@@ -298,12 +1124,14 @@ impl State for StateAlarmSongSet {
 				None
 			}
 			2 => {
-				*ALARM_SONG.write() = Some(self.midi_selector.curr().clone());
+				if let Some(alarm) = ALARMS.write().get_mut(self.alarm_index) {
+					alarm.song = Some(self.midi_selector.curr().clone());
+				}
 
 				self.player_sender.send(PlayerMessage::Stop)
 					.expect("Unable to stop currently playing");
 
-				Some(StateId::Clock)
+				Some(StateId::AlarmEdit(self.alarm_index))
 			}
 			_ => None,
 		}
@@ -324,38 +1152,387 @@ impl State for StateAlarmSongSet {
 	}
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TimerPhase {
+	Minutes,
+	Seconds,
+	Running,
+}
+
+/// Countdown timer, set in minutes then seconds. Once started it keeps running when the state is
+/// left, and rings like an alarm when it ends.
+pub struct StateTimer {
+	alphanum_sender: Sender<AlphanumMessage>,
+	player_sender: Sender<PlayerMessage>,
+	clock: Arc<dyn Clock>,
+	phase: TimerPhase,
+	minute_selector: BinarySelector<u16>,
+	second_selector: BinarySelector<u16>,
+}
+
+impl StateTimer {
+	pub fn new(
+		alphanum_sender: Sender<AlphanumMessage>,
+		player_sender: Sender<PlayerMessage>,
+		clock: Arc<dyn Clock>,
+	) -> Self {
+		let phase = if TIMER.read().is_some() {
+			TimerPhase::Running
+		} else {
+			TimerPhase::Minutes
+		};
+
+		Self {
+			alphanum_sender,
+			player_sender,
+			clock,
+			phase,
+			minute_selector: BinarySelector::new((0..100).collect()),
+			second_selector: BinarySelector::new((0..60).collect()),
+		}
+	}
+
+	fn send_phase(&mut self) {
+		let msg = match self.phase {
+			TimerPhase::Minutes => AlphanumMessage::Static(timer_text(*self.minute_selector.curr(), 'm')),
+			TimerPhase::Seconds => AlphanumMessage::Static(timer_text(*self.second_selector.curr(), 's')),
+			TimerPhase::Running => match &*TIMER.read() {
+				Some(timer) => AlphanumMessage::Countdown(timer.ends_at),
+				None => AlphanumMessage::Static("0000".to_string()),
+			}
+		};
+
+		self.alphanum_sender.send(msg).unwrap();
+	}
+
+	fn start(&mut self) {
+		let seconds = *self.minute_selector.curr() * 60 + *self.second_selector.curr();
+
+		*TIMER.write() = Some(Timer {
+			ends_at: self.clock.steady_now() + chrono::Duration::seconds(seconds.into()),
+			song: CONFIG.read().timer_song(),
+		});
+	}
+}
+
+/// Minutes or seconds followed by a letter saying which.
+fn timer_text(value: u16, unit: char) -> String {
+	format!("{:02}{} ", value.min(99), unit)
+}
+
+impl State for StateTimer {
+	fn init(&mut self) {
+		self.send_phase();
+	}
+
+	fn button_press(&mut self, button_id: u8) -> Option<StateId> {
+		// an ended timer, or anything else ringing, is dismissed like on the clock face
+		if self.phase == TimerPhase::Running {
+			let challenge = RINGING.read().as_ref().map(|ringing| ringing.challenge);
+
+			match challenge {
+				Some(Challenge::None) => {
+					RINGING.write().take();
+					self.player_sender.send(PlayerMessage::Stop)
+						.expect("Unable to stop alarm");
+
+					return Some(StateId::Clock);
+				}
+				Some(_) => return Some(StateId::Challenge),
+				None => (),
+			}
+		}
+
+		match (self.phase, button_id) {
+			(TimerPhase::Minutes, 0) => { self.minute_selector.incr(); }
+			(TimerPhase::Minutes, 1) => { self.minute_selector.decr(); }
+			(TimerPhase::Minutes, 2) => self.phase = TimerPhase::Seconds,
+			(TimerPhase::Seconds, 0) => { self.second_selector.incr(); }
+			(TimerPhase::Seconds, 1) => { self.second_selector.decr(); }
+			(TimerPhase::Seconds, 2) => {
+				self.start();
+				self.phase = TimerPhase::Running;
+			}
+			// down cancels the timer, mode leaves it running
+			(TimerPhase::Running, 0) => {
+				TIMER.write().take();
+				self.phase = TimerPhase::Minutes;
+			}
+			(TimerPhase::Running, 2) => return Some(StateId::Clock),
+			_ => return None,
+		}
+
+		self.send_phase();
+
+		None
+	}
+}
+
+/// Nap minutes change by this much at a time, up to the most that fit in two digits.
+const NAP_STEP_MINUTES: u32 = 5;
+const NAP_MAX_MINUTES: u32 = 95;
+
+/// Alarm for some minutes from now, kept apart from the normal alarms. Down and up change the
+/// minutes and mode sets it. While one is set it is shown instead, where down cancels it and mode
+/// leaves it set, both going back to the clock face.
+pub struct StateNap {
+	alphanum_sender: Sender<AlphanumMessage>,
+	clock: Arc<dyn Clock>,
+	minutes: u32,
+}
+
+impl StateNap {
+	pub fn new(alphanum_sender: Sender<AlphanumMessage>, clock: Arc<dyn Clock>) -> Self {
+		Self {
+			alphanum_sender,
+			clock,
+			minutes: CONFIG.read().nap_minutes,
+		}
+	}
+
+	fn send_nap(&mut self) {
+		let msg = match &*NAP.read() {
+			Some(nap) => {
+				let time = ClockTime::from(self.clock.steady_to_local(nap.ends_at).time());
+				AlphanumMessage::Loop(format!("Nap {}", time.text()))
+			}
+			None => AlphanumMessage::Static(timer_text(self.minutes as u16, 'm')),
+		};
+
+		self.alphanum_sender.send(msg).unwrap();
+	}
+}
+
+impl State for StateNap {
+	fn init(&mut self) {
+		self.send_nap();
+	}
+
+	fn button_press(&mut self, button_id: u8) -> Option<StateId> {
+		let is_set = NAP.read().is_some();
+
+		match (is_set, button_id) {
+			(true, 0) => {
+				NAP.write().take();
+
+				return Some(StateId::Clock);
+			}
+			(true, 2) => return Some(StateId::Clock),
+			(false, 0) => self.minutes = (self.minutes + NAP_STEP_MINUTES).min(NAP_MAX_MINUTES),
+			(false, 1) => self.minutes = self.minutes.saturating_sub(NAP_STEP_MINUTES).max(NAP_STEP_MINUTES),
+			(false, 2) => {
+				*NAP.write() = Some(Timer {
+					ends_at: self.clock.steady_now() + chrono::Duration::minutes(self.minutes.into()),
+					song: CONFIG.read().nap_song(),
+				});
+
+				return Some(StateId::Clock);
+			}
+			_ => return None,
+		}
+
+		self.send_nap();
+
+		None
+	}
+}
+
+/// Stopwatch. Down starts and stops it, up takes a lap while it runs or resets it while stopped, and
+/// mode goes back to the clock face.
+pub struct StateStopwatch {
+	alphanum_sender: Sender<AlphanumMessage>,
+	clock: Arc<dyn Clock>,
+	/// Time counted before the stopwatch was last started.
+	counted: chrono::Duration,
+	started: Option<NaiveDateTime>,
+	/// Time counted when each lap was taken.
+	laps: Vec<chrono::Duration>,
+}
+
+impl StateStopwatch {
+	pub fn new(alphanum_sender: Sender<AlphanumMessage>, clock: Arc<dyn Clock>) -> Self {
+		Self {
+			alphanum_sender,
+			clock,
+			counted: chrono::Duration::zero(),
+			started: None,
+			laps: Vec::new(),
+		}
+	}
+
+	/// Show the time counted, followed by each lap's time if any were taken.
+	fn send_stopped(&mut self) {
+		let chars = |time: chrono::Duration| duration_chars(time.num_seconds().max(0) as u64);
+
+		let msg = if self.laps.is_empty() {
+			AlphanumMessage::Static(chars(self.counted).iter().collect())
+		} else {
+			let mut text = format!("Time {}", chars(self.counted).iter().collect::<String>());
+			let mut lap_start = chrono::Duration::zero();
+
+			for (i, &lap) in self.laps.iter().enumerate() {
+				text += &format!(" Lap {} {}", i + 1, chars(lap - lap_start).iter().collect::<String>());
+				lap_start = lap;
+			}
+
+			AlphanumMessage::Loop(text)
+		};
+
+		self.alphanum_sender.send(msg).unwrap();
+	}
+}
+
+impl State for StateStopwatch {
+	fn init(&mut self) {
+		self.send_stopped();
+	}
+
+	fn button_press(&mut self, button_id: u8) -> Option<StateId> {
+		let now = self.clock.steady_now();
+
+		match (button_id, self.started) {
+			(0, Some(started)) => {
+				self.counted = self.counted + (now - started);
+				self.started = None;
+				self.send_stopped();
+			}
+			(0, None) => {
+				self.started = Some(now);
+				self.alphanum_sender.send(AlphanumMessage::Elapsed(now - self.counted)).unwrap();
+			}
+			(1, Some(started)) => self.laps.push(self.counted + (now - started)),
+			(1, None) => {
+				self.counted = chrono::Duration::zero();
+				self.laps.clear();
+				self.send_stopped();
+			}
+			(2, _) => return Some(StateId::Clock),
+			_ => (),
+		}
+
+		None
+	}
+}
+
+/// Sleep timer lengths in minutes, stepped through by pressing mode on the sleep entry in play mode.
+const SLEEP_MINUTES: [Option<u32>; 6] = [None, Some(15), Some(30), Some(45), Some(60), Some(90)];
+
 pub struct StatePlay {
 	alphanum_sender: Sender<AlphanumMessage>,
 	player_sender: Sender<PlayerMessage>,
-	midi_selector: LinearSelector<PathBuf>,
+	clock: Arc<dyn Clock>,
+	/// Songs to play, followed by `None` for the sleep timer entry.
+	midi_selector: LinearSelector<Option<PathBuf>>,
+	sleep_selector: LinearSelector<Option<u32>>,
+	/// Song last sent to the player, which keeps playing while the sleep entry is selected.
+	song: Option<PathBuf>,
+	playing: Option<String>,
 }
 
 impl StatePlay {
 	pub fn new(
 		alphanum_sender: Sender<AlphanumMessage>,
 		player_sender: Sender<PlayerMessage>,
+		clock: Arc<dyn Clock>,
 		midi_files: Vec<PathBuf>,
 	) -> Self {
+		let entries = midi_files.into_iter().map(Some).chain(std::iter::once(None)).collect();
+
 		Self {
 			alphanum_sender,
 			player_sender,
-			midi_selector: LinearSelector::new(midi_files.clone()),
+			clock,
+			midi_selector: LinearSelector::new(entries),
+			sleep_selector: LinearSelector::new(SLEEP_MINUTES.to_vec()),
+			song: None,
+			playing: None,
 		}
 	}
+
+	/// Show the minutes left on the sleep timer, rounded up.
+	fn send_sleep(&mut self) {
+		let text = match SLEEP.read().as_ref() {
+			Some(sleep) => {
+				let seconds = (sleep.ends_at - self.clock.steady_now()).num_seconds().max(0) as u64;
+				format!("Sleep {}", seconds.div_ceil(60))
+			}
+			None => "Sleep Off".to_owned(),
+		};
+
+		self.alphanum_sender.send(AlphanumMessage::Loop(text)).unwrap();
+	}
+
+	/// Set the sleep timer to the next length, turning it off after the longest.
+	fn next_sleep(&mut self) {
+		// the timer may have run out since it was last chosen
+		if SLEEP.read().is_none() {
+			self.sleep_selector.reset();
+		}
+
+		let minutes = *self.sleep_selector.incr();
+
+		let old = SLEEP.write().take();
+		if old.as_ref().is_some_and(|sleep| sleep.speed != 1.0) {
+			self.player_sender.send(PlayerMessage::Speed(1.0)).unwrap();
+		}
+
+		if let Some(minutes) = minutes {
+			*SLEEP.write() = Some(SleepTimer {
+				ends_at: self.clock.steady_now() + chrono::Duration::minutes(minutes.into()),
+				speed: 1.0,
+			});
+
+			// loop the song so it keeps playing until the timer ends
+			if let (None, Some(_), Some(song)) = (old, &self.playing, &self.song) {
+				self.player_sender.send(PlayerMessage::Loop(song.clone())).unwrap();
+			}
+		}
+
+		self.send_sleep();
+	}
+
+	/// Loop songs while the sleep timer is running, otherwise play them once.
+	fn play(&mut self, song: PathBuf) {
+		self.song = Some(song.clone());
+
+		let msg = if SLEEP.read().is_some() {
+			PlayerMessage::Loop(song)
+		} else {
+			PlayerMessage::Play(song)
+		};
+
+		self.player_sender.send(msg).expect("Unable to send midi file name");
+	}
+
+	fn on_sleep_entry(&self) -> bool {
+		self.midi_selector.curr().is_none()
+	}
 }
 
 impl State for StatePlay {
+	fn finish(&mut self) {
+		// the player goes back to normal speed when it's stopped
+		SLEEP.write().take();
+	}
+
 	fn button_press(&mut self, button_id: u8) -> Option<StateId> {
 		match button_id {
 			0 | 1 => {
-				let midi_file = if button_id == 0 {
+				let entry = if button_id == 0 {
 					self.midi_selector.incr()
 				} else {
 					self.midi_selector.decr()
-				};
+				}.clone();
 
-				self.player_sender.send(PlayerMessage::Play(midi_file.clone()))
-					.expect("Unable to send midi file name");
+				match entry {
+					Some(midi_file) => self.play(midi_file),
+					None => self.send_sleep(),
+				}
+
+				None
+			}
+			2 if self.on_sleep_entry() => {
+				self.next_sleep();
 
 				None
 			}
@@ -371,14 +1548,22 @@ impl State for StatePlay {
 
 	fn song_start(&mut self, name: String) -> Option<StateId> {
 		println!("Now playing {:?}", name);
-		self.alphanum_sender.send(AlphanumMessage::Loop(name)).unwrap();
+		self.playing = Some(name.clone());
+
+		if !self.on_sleep_entry() {
+			self.alphanum_sender.send(AlphanumMessage::Loop(name)).unwrap();
+		}
 
 		None
 	}
 
 	fn song_end(&mut self, name: String) -> Option<StateId> {
 		println!("Stopped playing {:?}", name);
-		self.alphanum_sender.send(AlphanumMessage::Loop("Play".to_string())).unwrap();
+		self.playing = None;
+
+		if !self.on_sleep_entry() {
+			self.alphanum_sender.send(AlphanumMessage::Loop("Play".to_string())).unwrap();
+		}
 
 		None
 	}
@@ -1,12 +1,42 @@
 #![cfg(test)]
 
 use std::convert::TryInto;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::sync::{ mpsc, Arc };
+use std::thread;
 use std::time;
 
-use crate::{ MidiNote, CONFIG };
+use chrono::{ NaiveDate, NaiveDateTime, NaiveTime, Utc };
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::{ MidiNote, CONFIG, ALARMS, RINGING, ClockTime };
+use crate::alarm::{ Alarm, Challenge, Repeat, Ringing };
+use crate::clock::{ Clock, RtcClock, VirtualClock, ZonedClock };
 use crate::note::Note;
-use crate::circuit::{ Alphanum, Buzzer };
+use crate::circuit::{ glyph_text, glyphs, Alphanum, AlphanumDisplay, Buzzer, Ds3231, Font, Glyph, ToneOutput };
+use crate::circuit::mock::{ MockAlphanum, MockBuzzer, MockI2c, MockInput, ToneEvent };
+use crate::circuit::terminal::segment_lines;
+use crate::saved::SavedState;
+use crate::scenario::Scenario;
+use crate::config::{ Config, Escalation };
+use crate::message::{ AlarmEvent, AlphanumMessage, BuzzerMessage, ButtonEvent, EventMessage, PlayerMessage };
 use crate::selector::{ BinarySelector, LinearSelector, Selector };
+use crate::states::{ State, StateClockSet, StateId };
+use crate::threads::alarm::{ alarm_song, alarm_thread, escalate_steps, ring_since, wake_song };
+use crate::threads::alphanum::alphanum_thread;
+use crate::threads::buzzer::update_buzzer;
+use crate::threads::input::poll_inputs;
+use crate::threads::player::{ song_messages, song_name };
+use crate::time_format::{
+	clock_face_at, clock_face_chars, duration_chars, marker_due, pair_chars, pm_dot_glyphs, scroll_chars, ClockFace,
+	Separator,
+};
+use crate::wav::{ WavRenderer, SAMPLE_RATE };
+
+/// Held by tests which use the global alarm settings.
+static ALARM_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
 
 macro_rules! assert_delta {
 	($x:expr, $y:expr, $d:expr) => {
@@ -26,6 +56,15 @@ fn test_freqencies() {
 	assert_delta!(dbg!(MidiNote(-1).frequency()), 7.71, delta);
 }
 
+#[test]
+fn test_note_names() {
+	assert_eq!(MidiNote(60).name(), "C4");
+	assert_eq!(MidiNote(61).name(), "C#4");
+	assert_eq!(MidiNote(69).name(), "A4");
+	assert_eq!(MidiNote(11).name(), "B-1");
+	assert_eq!(MidiNote(-1).name(), "B-2");
+}
+
 /// qualitative test only
 #[test] #[ignore]
 fn test_range() -> rppal::gpio::Result<()> {
@@ -49,19 +88,22 @@ fn test_range() -> rppal::gpio::Result<()> {
 /// qualitative test only
 #[test] #[ignore]
 fn test_alphanum() -> rppal::i2c::Result<()> {
-	let mut alphanum = Alphanum::new()?;
+	let mut alphanum = {
+		let config = CONFIG.read();
+		Alphanum::new(config.display_bus(), config.display_addresses())?
+	};
 
 	let string = "    !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~    ";
 	let mut iter = string.chars();
 
 	loop {
-		let chars = iter.clone().take(4).collect::<Vec<_>>();
+		let chars = iter.clone().take(alphanum.width()).collect::<Vec<_>>();
 
-		if chars.len() != 4 {
+		if chars.len() != alphanum.width() {
 			break;
 		}
 
-		alphanum.display(&chars.try_into().unwrap())?;
+		alphanum.display(&chars)?;
 		iter.next();
 
 		std::thread::sleep(time::Duration::from_millis(300));
@@ -168,3 +210,812 @@ fn test_binary_selector() {
 		}
 	}
 }
+
+#[test]
+fn test_mock_buzzer() {
+	let (buzzer, tones) = MockBuzzer::new();
+	let (note_sender, note_receiver) = mpsc::channel();
+
+	let thread = thread::spawn(move || update_buzzer(note_receiver, buzzer));
+
+	note_sender.send(BuzzerMessage::Note { on: true, note: MidiNote(60) }).unwrap();
+	note_sender.send(BuzzerMessage::Note { on: true, note: MidiNote(64) }).unwrap();
+	note_sender.send(BuzzerMessage::Note { on: false, note: MidiNote(60) }).unwrap();
+	note_sender.send(BuzzerMessage::Clear).unwrap();
+	drop(note_sender);
+
+	thread.join().unwrap();
+
+	assert_eq!(
+		tones.iter().collect::<Vec<_>>(),
+		vec![
+			ToneEvent::On(MidiNote(60)),
+			ToneEvent::On(MidiNote(64)),
+			ToneEvent::Off(MidiNote(60)),
+			ToneEvent::Clear,
+			// cleared again when the channel disconnects
+			ToneEvent::Clear,
+		],
+	);
+}
+
+#[test]
+fn test_mock_inputs() {
+	let (input, button_sender) = MockInput::new();
+	let (event_sender, event_receiver) = mpsc::channel();
+
+	let thread = thread::spawn(move || poll_inputs(event_sender, input));
+
+	button_sender.send(ButtonEvent::Press(0)).unwrap();
+	button_sender.send(ButtonEvent::Press(1)).unwrap();
+	// within the bounce time of the first press, so held back until it is up
+	button_sender.send(ButtonEvent::Release(0)).unwrap();
+	// bouncing back to where the button was sends nothing
+	button_sender.send(ButtonEvent::Release(1)).unwrap();
+	button_sender.send(ButtonEvent::Press(1)).unwrap();
+
+	let events = event_receiver.iter()
+		.take(3)
+		.map(|e| match e {
+			EventMessage::Button(ButtonEvent::Press(i)) => (true, i),
+			EventMessage::Button(ButtonEvent::Release(i)) => (false, i),
+			e => panic!("Unexpected event {:?}", e),
+		})
+		.collect::<Vec<_>>();
+
+	assert_eq!(events, vec![(true, 0), (true, 1), (false, 0)]);
+	assert!(event_receiver.recv_timeout(time::Duration::from_millis(500)).is_err());
+
+	drop(button_sender);
+	assert!(thread.join().unwrap().is_err());
+}
+
+#[test]
+fn test_mock_alphanum() {
+	let (mut alphanum, frames) = MockAlphanum::new();
+	let (alphanum_sender, alphanum_receiver) = mpsc::channel();
+
+	alphanum.ascii_uppercase(true);
+
+	let clock = Arc::new(VirtualClock::new(at(1, 0, 0)));
+	let thread = thread::spawn(move || alphanum_thread(alphanum, alphanum_receiver, clock));
+
+	alphanum_sender.send(AlphanumMessage::Static("ab12".to_string())).unwrap();
+	alphanum_sender.send(AlphanumMessage::Glyphs(glyphs("1.2.3a."))).unwrap();
+	alphanum_sender.send(AlphanumMessage::Empty).unwrap();
+	drop(alphanum_sender);
+
+	thread.join().unwrap().unwrap();
+
+	let frames = frames.iter().map(|frame| glyph_text(&frame)).collect::<Vec<_>>();
+
+	assert!(frames.contains(&"AB12".to_string()));
+	assert!(frames.contains(&"1.2.3A.".to_string()));
+	assert_eq!(frames.last().map(String::as_str), Some("    "));
+}
+
+#[test]
+fn test_wide_alphanum() {
+	let (alphanum, frames) = MockAlphanum::with_width(8);
+	let (alphanum_sender, alphanum_receiver) = mpsc::channel();
+	let clock = Arc::new(VirtualClock::new(at(1, 7, 30)));
+
+	let thread = thread::spawn(move || alphanum_thread(alphanum, alphanum_receiver, clock));
+
+	// static text fills the display from the left, and is cut short if it is too wide
+	alphanum_sender.send(AlphanumMessage::Static("Tue 1 Jun".to_string())).unwrap();
+	wait_for_frame(&frames, "Tue 1 Ju");
+	alphanum_sender.send(AlphanumMessage::Static("0730".to_string())).unwrap();
+	wait_for_frame(&frames, "0730    ");
+
+	// looping text scrolls across the whole width, starting from the configured offset
+	alphanum_sender.send(AlphanumMessage::Loop("Alarm 1".to_string())).unwrap();
+	let offset = CONFIG.read().text_offset.rem_euclid(15) as usize;
+	let text = "Alarm 1        Alarm 1        ";
+	wait_for_frame(&frames, &text[offset..offset + 8]);
+	wait_for_frame(&frames, &text[offset + 1..offset + 9]);
+
+	alphanum_sender.send(AlphanumMessage::Time).unwrap();
+	wait_for_frame(&frames, "0730    ");
+
+	drop(alphanum_sender);
+	thread.join().unwrap().unwrap();
+}
+
+#[test]
+fn test_glyphs() {
+	// a dot lights the decimal point of the character before it, unless that is already lit
+	let dotted = glyphs("12.34");
+	assert_eq!(dotted.len(), 4);
+	assert!(dotted[1].dot && !dotted[0].dot && !dotted[2].dot);
+	assert_eq!(glyphs(".1..").iter().map(|glyph| (glyph.c, glyph.dot)).collect::<Vec<_>>(), vec![(' ', true), ('1', true), (' ', true)]);
+	assert_eq!(glyph_text(&glyphs(".1..")), " .1. .");
+
+	assert_eq!(Font::default().segments(Glyph::from('8')), 0b_0000_0000_1111_1111);
+	assert_eq!(Font::default().segments(Glyph { c: '8', dot: true }), 0b_0100_0000_1111_1111);
+	assert_eq!(glyph_text(&pm_dot_glyphs([' ', '7', '3', '0'], true)), " 730.");
+	assert_eq!(glyph_text(&pm_dot_glyphs([' ', '7', '3', '0'], false)), " 730");
+
+	// separators go between pairs of digits, blinking for the first half of each second, and the
+	// seconds face always has its dot
+	assert!(ClockFace::HoursMinutes.dot_lit(Separator::Dot, 0) && !ClockFace::HoursMinutes.dot_lit(Separator::None, 0));
+	assert!(ClockFace::Seconds.dot_lit(Separator::None, 0) && !ClockFace::Date.dot_lit(Separator::Dot, 0));
+	assert!(!Separator::None.lit(0));
+	assert!(Separator::Dot.lit(750));
+	assert!(Separator::Blink.lit(1_250) && !Separator::Blink.lit(1_750));
+}
+
+#[test]
+fn test_font() {
+	let font: Font = toml::from_str(r#"
+		[glyphs]
+		"°" = 0b0000_0000_1110_0011
+		"8" = 0b0000_0000_0000_0001
+
+		[substitutions]
+		"é" = "e"
+		"°" = "o"
+		"ø" = "ö"
+	"#).unwrap();
+
+	// the font's glyphs come before the built-in ones
+	assert_eq!(font.segments(Glyph::from('°')), 0b_0000_0000_1110_0011);
+	assert_eq!(font.segments(Glyph::from('8')), 0b_0000_0000_0000_0001);
+	assert_eq!(font.segments(Glyph::from('7')), 0b_0000_0000_0000_0111);
+	assert_eq!(Font::default().segments(Glyph::from('°')), 0b_0011_1111_1111_1111);
+
+	// and substitutions are only for characters without segments
+	assert_eq!(font.substitute('é'), 'e');
+	assert_eq!(font.substitute('°'), '°');
+	assert_eq!(font.substitute('ø'), 'ö');
+	assert_eq!(font.segments(Glyph::from(font.substitute('ø'))), 0b_0011_1111_1111_1111);
+	assert_eq!(font.render(Glyph { c: 'é', dot: true }, true), font.segments(Glyph { c: 'E', dot: true }));
+
+	// the font that comes with the clock reads
+	let font: Font = toml::from_str(&fs::read_to_string("font.toml").unwrap()).unwrap();
+	assert_eq!(font.substitute('É'), 'E');
+
+	// substituted characters are made uppercase too
+	let (mut alphanum, frames) = MockAlphanum::new();
+	alphanum.ascii_uppercase(true);
+	alphanum.font(font);
+	alphanum.display(&['C', 'a', 'f', 'é']).unwrap();
+	assert_eq!(frames.try_iter().map(|frame| glyph_text(&frame)).collect::<Vec<_>>(), vec!["CAFE"]);
+}
+
+#[test]
+fn test_rtc() {
+	let (i2c, registers) = MockI2c::new(0x13);
+	let mut rtc = Ds3231::new(i2c);
+
+	// a clock which was never set has lost the time
+	registers.lock()[0x0F] = 0x80;
+	assert_eq!(rtc.read_time(), Ok(None));
+
+	// saturday, in binary coded decimal
+	rtc.write_time(at(5, 23, 59) + chrono::Duration::seconds(30)).unwrap();
+	assert_eq!(registers.lock()[..7], [0x30, 0x59, 0x23, 0x06, 0x05, 0x06, 0x21]);
+	assert_eq!(registers.lock()[0x0F], 0x00);
+	assert_eq!(rtc.read_time(), Ok(Some(at(5, 23, 59) + chrono::Duration::seconds(30))));
+
+	// 12 hour mode, 7pm
+	registers.lock()[2] = 0x40 | 0x20 | 0x07;
+	assert_eq!(rtc.read_time(), Ok(Some(at(5, 19, 59) + chrono::Duration::seconds(30))));
+
+	// the clock is set from it at startup, and sets it whenever the clock is set
+	let rtc_clock = RtcClock::new(Arc::new(VirtualClock::new(at(1, 0, 0))), rtc);
+	assert_eq!(rtc_clock.now(), at(5, 19, 59) + chrono::Duration::seconds(30));
+
+	rtc_clock.set_time(ClockTime::new(6 * 60 + 45));
+	assert_eq!(rtc_clock.now(), at(5, 6, 45));
+	assert_eq!(registers.lock()[..3], [0x00, 0x45, 0x06]);
+
+	// with a time zone it keeps UTC, an hour behind London in the summer
+	let (i2c, registers) = MockI2c::new(0x13);
+	let mut rtc = Ds3231::new(i2c);
+	rtc.write_time(at(5, 19, 59)).unwrap();
+
+	let zoned = Arc::new(ZonedClock::new(chrono_tz::Europe::London, chrono::Duration::zero()));
+	let rtc_clock = RtcClock::new(zoned, rtc);
+	assert_delta!((rtc_clock.now() - at(5, 20, 59)).num_milliseconds(), 0, 1000);
+
+	rtc_clock.set_time(ClockTime::new(6 * 60 + 45));
+	assert_eq!(registers.lock()[1..3], [0x45, 0x05]);
+}
+
+#[test]
+fn test_segment_lines() {
+	assert_eq!(segment_lines(0b_0000_1100_0011_1111), [
+		" ---  ",
+		"|  /| ",
+		"      ",
+		"|/  | ",
+		" ---  ",
+	]);
+
+	assert_eq!(segment_lines(0b_0111_1111_1111_1111), [
+		" ---  ",
+		"|\\|/| ",
+		" - -  ",
+		"|/|\\| ",
+		" --- .",
+	]);
+}
+
+fn wav_samples(renderer: &WavRenderer) -> Vec<i16> {
+	let mut bytes = Vec::new();
+	renderer.write(&mut bytes).unwrap();
+
+	assert_eq!(&bytes[0..4], b"RIFF");
+	assert_eq!(&bytes[8..16], b"WAVEfmt ");
+	assert_eq!(u32::from_le_bytes(bytes[24..28].try_into().unwrap()), SAMPLE_RATE);
+	assert_eq!(&bytes[36..40], b"data");
+	assert_eq!(u32::from_le_bytes(bytes[40..44].try_into().unwrap()) as usize, bytes.len() - 44);
+
+	bytes[44..].chunks(2)
+		.map(|b| i16::from_le_bytes(b.try_into().unwrap()))
+		.collect()
+}
+
+#[test]
+fn test_wav_render() {
+	let mut renderer = WavRenderer::new();
+
+	// one second of A4, half a second of silence, then A4 and A5 together for a second
+	renderer.apply(BuzzerMessage::Note { on: true, note: MidiNote(69) });
+	renderer.render_until(time::Duration::from_secs(1));
+	renderer.apply(BuzzerMessage::Clear);
+	renderer.render_until(time::Duration::from_millis(1500));
+	renderer.apply(BuzzerMessage::Note { on: true, note: MidiNote(69) });
+	renderer.apply(BuzzerMessage::Note { on: true, note: MidiNote(81) });
+	renderer.render_until(time::Duration::from_millis(2500));
+
+	let samples = wav_samples(&renderer);
+	let rate = SAMPLE_RATE as usize;
+	let pulses = |range: std::ops::Range<usize>| samples[range].iter().filter(|&&s| s != 0).count();
+
+	assert_eq!(samples.len(), rate * 5 / 2);
+	assert!((439..=440).contains(&pulses(0..rate)));
+	assert_eq!(pulses(rate..rate * 3 / 2), 0);
+	// A4 pulses mostly land on the same sample as an A5 pulse
+	assert!((879..=1320).contains(&dbg!(pulses(rate * 3 / 2..rate * 5 / 2))));
+}
+
+#[test]
+fn test_render_song() {
+	let midi = fs::read("../midi/Worms.mid").unwrap();
+	let smf = midly::Smf::parse(&midi).unwrap();
+	let messages = song_messages(&smf);
+
+	// songs are walked in time order, and end with every note off
+	assert!(messages.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+	assert!(matches!(messages.last(), Some((_, BuzzerMessage::Clear))));
+
+	// so a render only depends on the song, and is as long as it is
+	let end = messages.last().unwrap().0;
+	let samples = wav_samples(&WavRenderer::render_messages(messages));
+	assert_eq!(samples, wav_samples(&WavRenderer::render_messages(song_messages(&smf))));
+	assert_eq!(samples.len(), (end.as_secs_f64() * SAMPLE_RATE as f64) as usize);
+	assert!(samples.iter().any(|&s| s != 0));
+}
+
+fn minutes(minutes: u64) -> time::Duration {
+	time::Duration::from_secs(minutes * 60)
+}
+
+/// Time on a day in June 2021, which started on a Tuesday.
+fn at(day: u32, hours: u32, minutes: u32) -> NaiveDateTime {
+	NaiveDate::from_ymd(2021, 6, day).and_hms(hours, minutes, 0)
+}
+
+/// Wait for the display to show `expected`, failing if it takes more than a second.
+fn wait_for_frame(frames: &mpsc::Receiver<Vec<Glyph>>, expected: &str) {
+	let deadline = time::Instant::now() + time::Duration::from_secs(1);
+
+	while let Some(timeout) = deadline.checked_duration_since(time::Instant::now()) {
+		if frames.recv_timeout(timeout).map(|frame| glyph_text(&frame)).as_deref() == Ok(expected) {
+			return;
+		}
+	}
+
+	panic!("Display never showed {:?}", expected);
+}
+
+#[test]
+fn test_alphanum_time() {
+	let (alphanum, frames) = MockAlphanum::new();
+	let (alphanum_sender, alphanum_receiver) = mpsc::channel();
+	let clock = Arc::new(VirtualClock::new(at(1, 12, 34)));
+
+	let thread = {
+		let clock = clock.clone();
+		thread::spawn(move || alphanum_thread(alphanum, alphanum_receiver, clock))
+	};
+
+	alphanum_sender.send(AlphanumMessage::Time).unwrap();
+	wait_for_frame(&frames, "1234");
+
+	clock.advance(minutes(26));
+	wait_for_frame(&frames, "1300");
+
+	// past midnight wraps around to the next day
+	clock.advance(minutes(11 * 60 + 1));
+	wait_for_frame(&frames, "0001");
+
+	// countdowns round up to the next second, and stop at zero
+	alphanum_sender.send(AlphanumMessage::Countdown(clock.now() + chrono::Duration::milliseconds(90_500))).unwrap();
+	wait_for_frame(&frames, "0131");
+
+	clock.advance(time::Duration::from_millis(90_000));
+	wait_for_frame(&frames, "0001");
+
+	clock.advance(minutes(1));
+	wait_for_frame(&frames, "0000");
+
+	drop(alphanum_sender);
+	thread.join().unwrap().unwrap();
+}
+
+/// Stop an alarm thread by making it send to a closed channel, so it can't ring alarms set up by
+/// later tests.
+fn stop_alarm_thread(thread: thread::JoinHandle<()>, player_receiver: mpsc::Receiver<PlayerMessage>) {
+	drop(player_receiver);
+
+	let mut ringing = Ringing::new(PathBuf::from("stop.mid"), Challenge::None, at(1, 0, 0));
+	ringing.snoozed_until = Some(at(1, 0, 0));
+	*RINGING.write() = Some(ringing);
+
+	thread.join().unwrap();
+	RINGING.write().take();
+}
+
+#[test]
+fn test_alarm_thread() {
+	let (player_sender, player_receiver) = mpsc::channel();
+	let (event_sender, _event_receiver) = mpsc::channel();
+	let clock = Arc::new(VirtualClock::new(at(1, 6, 59)));
+	let song = PathBuf::from("alarm.mid");
+
+	let mut disabled = Alarm::new(ClockTime::new(7 * 60 + 1), Some(PathBuf::from("disabled.mid")));
+	disabled.enabled = false;
+
+	let _lock = ALARM_LOCK.lock();
+	*ALARMS.write() = vec![
+		disabled,
+		Alarm::new(ClockTime::new(7 * 60), Some(song.clone())),
+		Alarm::new(ClockTime::new(7 * 60 + 2), Some(PathBuf::from("later.mid"))),
+	];
+
+	RINGING.write().take();
+
+	let thread = {
+		let clock = clock.clone();
+		thread::spawn(move || alarm_thread(player_sender, event_sender, clock))
+	};
+
+	let timeout = time::Duration::from_millis(500);
+
+	assert!(player_receiver.recv_timeout(timeout).is_err());
+
+	clock.advance(minutes(1));
+	match player_receiver.recv_timeout(timeout) {
+		Ok(PlayerMessage::Loop(path)) => assert_eq!(path, song),
+		msg => panic!("Expected alarm to loop, got {:?}", msg),
+	}
+
+	// only fires when the alarm time is reached, not for the whole minute
+	assert!(player_receiver.recv_timeout(timeout).is_err());
+
+	// disabled alarms are skipped
+	clock.advance(minutes(1));
+	assert!(player_receiver.recv_timeout(timeout).is_err());
+
+	clock.advance(minutes(1));
+	match player_receiver.recv_timeout(timeout) {
+		Ok(PlayerMessage::Loop(path)) => assert_eq!(path, PathBuf::from("later.mid")),
+		msg => panic!("Expected second alarm to loop, got {:?}", msg),
+	}
+	assert_eq!(RINGING.read().as_ref().map(|ringing| ringing.song.clone()), Some(PathBuf::from("later.mid")));
+
+	// snoozed alarms go off again once the snooze runs out
+	RINGING.write().as_mut().unwrap().snoozed_until = Some(at(1, 7, 11));
+
+	clock.advance(minutes(8));
+	assert!(player_receiver.recv_timeout(timeout).is_err());
+
+	clock.advance(minutes(1));
+	match player_receiver.recv_timeout(timeout) {
+		Ok(PlayerMessage::Loop(path)) => assert_eq!(path, PathBuf::from("later.mid")),
+		msg => panic!("Expected snoozed alarm to loop, got {:?}", msg),
+	}
+	assert_eq!(RINGING.read().as_ref().unwrap().snoozed_until, None);
+
+	stop_alarm_thread(thread, player_receiver);
+}
+
+#[test]
+fn test_escalate() {
+	let steps = [
+		Escalation::Song { after_minutes: 10, song: PathBuf::from("harsh.mid") },
+		Escalation::Stop { after_minutes: 30 },
+	];
+	let midi_dir = Path::new("midi");
+
+	let _lock = ALARM_LOCK.lock();
+	*RINGING.write() = Some(Ringing::new(PathBuf::from("alarm.mid"), Challenge::None, at(1, 7, 0)));
+
+	// unanswered alarms take each step in turn, timed from when they last went off
+	assert!(escalate_steps(at(1, 7, 9), &steps, midi_dir).is_none());
+	match escalate_steps(at(1, 7, 10), &steps, midi_dir) {
+		Some((PlayerMessage::Loop(path), AlarmEvent::Escalate(name))) => {
+			assert_eq!(path, midi_dir.join("harsh.mid"));
+			assert_eq!(name, song_name(&path));
+			assert_eq!(RINGING.read().as_ref().unwrap().song, path);
+		}
+		step => panic!("Expected alarm to escalate, got {:?}", step),
+	}
+	assert!(escalate_steps(at(1, 7, 29), &steps, midi_dir).is_none());
+
+	// snoozed alarms wait
+	RINGING.write().as_mut().unwrap().snoozed_until = Some(at(1, 7, 40));
+	assert!(escalate_steps(at(1, 7, 30), &steps, midi_dir).is_none());
+	RINGING.write().as_mut().unwrap().snoozed_until = None;
+
+	assert!(matches!(escalate_steps(at(1, 7, 30), &steps, midi_dir), Some((PlayerMessage::Stop, AlarmEvent::Stop))));
+	assert_eq!(*RINGING.read(), None);
+	assert!(escalate_steps(at(1, 8, 0), &steps, midi_dir).is_none());
+}
+
+#[test]
+fn test_clock_set() {
+	let (alphanum_sender, alphanum_receiver) = mpsc::channel();
+	let clock = Arc::new(VirtualClock::new(at(1, 3, 0)));
+	let mut state = StateClockSet::new(alphanum_sender, clock.clone());
+
+	state.init();
+	assert!(state.button_press(0).is_none());
+	assert!(state.button_press(1).is_none());
+	assert!(matches!(state.button_press(2), Some(StateId::Clock)));
+
+	let frames = alphanum_receiver.try_iter()
+		.map(|msg| match msg {
+			AlphanumMessage::Static(text) => text,
+			msg => panic!("Unexpected message {:?}", msg),
+		})
+		.collect::<Vec<_>>();
+
+	assert_eq!(frames, vec!["1200", "1800", "1500"]);
+	assert_eq!(clock.clock_time(), ClockTime::new(15 * 60));
+	assert_eq!(clock.now(), at(1, 15, 0));
+}
+
+#[test]
+fn test_config_defaults() {
+	// a config file from before any of the newer settings
+	let config: Config = toml::from_str(r#"
+		buzzer_pin = 12
+		button_pins = [ 5, 6, 13 ]
+		midi_dir = "midi/"
+		button_bounce_ms = 200
+		brightness = 7
+		scroll_delay_ms = 250
+		ascii_uppercase = true
+		text_offset = -2
+	"#).unwrap();
+
+	assert_eq!(config.state_file(), None);
+	assert_eq!(config.display_addresses(), &[0x70]);
+	assert_eq!(config.clock_faces, vec![ClockFace::HoursMinutes]);
+	assert!(!config.twelve_hour && !config.rtc);
+	assert_eq!(config.dismiss_challenge, Challenge::None);
+	assert_eq!(config.time_zone(), None);
+	assert!(config.escalation.is_empty());
+}
+
+#[test]
+fn test_config_checks() {
+	let parse = |extra: &str| toml::from_str::<Config>(&format!(r#"
+		buzzer_pin = 12
+		button_pins = [ 5, 6, 13 ]
+		midi_dir = "midi/"
+		button_bounce_ms = 200
+		brightness = 7
+		scroll_delay_ms = 250
+		ascii_uppercase = true
+		text_offset = -2
+		{}
+	"#, extra));
+
+	// settings which could only fail once the clock is running are checked as the config loads
+	assert_eq!(parse(r#"time_zone = "Europe/London""#).unwrap().time_zone(), Some(chrono_tz::Europe::London));
+	assert!(parse(r#"time_zone = "Europe/Londn""#).is_err());
+	assert_eq!(parse(r#"date_format = "%d/%m""#).unwrap().date_format, "%d/%m");
+	assert!(parse(r#"date_format = "%d %""#).is_err());
+	assert!(parse(r#"date_format = "%Q""#).is_err());
+	assert_eq!(parse("display_addresses = [0x70, 0x71]").unwrap().display_width(), 8);
+	assert!(parse("display_addresses = []").is_err());
+
+	// but a font file that can't be loaded only leaves the built-in characters
+	assert_eq!(parse(r#"font_file = "missing.toml""#).unwrap().font(), Font::default());
+	assert_eq!(parse(r#"font_file = "scenarios/play.txt""#).unwrap().font(), Font::default());
+}
+
+#[test]
+fn test_scenarios() {
+	let _lock = ALARM_LOCK.lock();
+
+	let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+	let mut paths = fs::read_dir(&dir).unwrap()
+		.map(|f| f.unwrap().path())
+		.collect::<Vec<_>>();
+	paths.sort();
+
+	assert!(!paths.is_empty());
+
+	for path in paths {
+		let script = fs::read_to_string(&path).unwrap();
+
+		Scenario::new().run(&path.file_name().unwrap().to_string_lossy(), &script);
+	}
+}
+
+#[test]
+fn test_alarm_recurrence() {
+	let (player_sender, player_receiver) = mpsc::channel();
+	let (event_sender, _event_receiver) = mpsc::channel();
+	let clock = Arc::new(VirtualClock::new(at(4, 6, 59)));
+
+	let mut weekdays = Alarm::new(ClockTime::new(7 * 60), Some(PathBuf::from("weekdays.mid")));
+	weekdays.repeat = Repeat::Weekdays;
+	let mut weekends = Alarm::new(ClockTime::new(7 * 60), Some(PathBuf::from("weekends.mid")));
+	weekends.repeat = Repeat::Weekends;
+	let mut monday = Alarm::new(ClockTime::new(8 * 60), Some(PathBuf::from("monday.mid")));
+	monday.repeat = Repeat::Days(Repeat::day_bit(chrono::Weekday::Mon));
+	let mut once = Alarm::new(ClockTime::new(9 * 60), Some(PathBuf::from("once.mid")));
+	once.repeat = Repeat::Once;
+
+	let _lock = ALARM_LOCK.lock();
+	*ALARMS.write() = vec![weekdays, weekends, monday, once];
+	RINGING.write().take();
+
+	let thread = {
+		let clock = clock.clone();
+		thread::spawn(move || alarm_thread(player_sender, event_sender, clock))
+	};
+
+	let timeout = time::Duration::from_millis(300);
+	let ring_at = |now: NaiveDateTime| {
+		// dismiss the last alarm before it escalates
+		RINGING.write().take();
+		clock.set(now - chrono::Duration::minutes(1));
+		thread::sleep(timeout);
+		clock.set(now);
+
+		match player_receiver.recv_timeout(timeout) {
+			Ok(PlayerMessage::Loop(path)) => Some(path.to_string_lossy().into_owned()),
+			Ok(msg) => panic!("Unexpected message {:?}", msg),
+			Err(_) => None,
+		}
+	};
+
+	// friday, saturday, sunday, monday
+	assert_eq!(ring_at(at(4, 7, 0)).as_deref(), Some("weekdays.mid"));
+	assert_eq!(ring_at(at(5, 7, 0)).as_deref(), Some("weekends.mid"));
+	assert_eq!(ring_at(at(6, 7, 0)).as_deref(), Some("weekends.mid"));
+	assert_eq!(ring_at(at(7, 7, 0)).as_deref(), Some("weekdays.mid"));
+
+	assert_eq!(ring_at(at(6, 8, 0)), None);
+	assert_eq!(ring_at(at(7, 8, 0)).as_deref(), Some("monday.mid"));
+
+	// one shot alarms turn off after going off
+	assert_eq!(ring_at(at(7, 9, 0)).as_deref(), Some("once.mid"));
+	assert!(!ALARMS.read()[3].enabled);
+	assert_eq!(ring_at(at(8, 9, 0)), None);
+
+	stop_alarm_thread(thread, player_receiver);
+}
+
+#[test]
+fn test_saved_state() {
+	let dir = std::env::temp_dir().join(format!("mus-test-{}", std::process::id()));
+	fs::create_dir_all(&dir).unwrap();
+	let path = dir.join("state.toml");
+
+	let mut days = Alarm::new(ClockTime::new(7 * 60 + 30), Some(PathBuf::from("midi/song.mid")));
+	days.repeat = Repeat::Days(Repeat::day_bit(chrono::Weekday::Tue) | Repeat::day_bit(chrono::Weekday::Sat));
+	let mut silent = Alarm::new(ClockTime::new(23 * 60), None);
+	silent.enabled = false;
+	silent.repeat = Repeat::Once;
+
+	let saved = SavedState { clock_offset: -90, alarms: vec![days, silent] };
+
+	// missing files use the defaults
+	assert_eq!(SavedState::load(&path), SavedState::default());
+
+	saved.save(&path).unwrap();
+	assert_eq!(SavedState::load(&path), saved);
+	assert_eq!(saved.clock_offset(), chrono::Duration::seconds(-90));
+
+	// as do files which can't be parsed
+	fs::write(&path, "alarms = [ { time = \"seven\" } ]").unwrap();
+	assert_eq!(SavedState::load(&path), SavedState::default());
+
+	// out of range times wrap around like any other clock time
+	fs::write(&path, "[[alarms]]\ntime = 1500\nenabled = true\nrepeat = \"Daily\"\n").unwrap();
+	assert_eq!(SavedState::load(&path).alarms, vec![Alarm { song: None, ..Alarm::new(ClockTime::new(60), None) }]);
+
+	// times are saved as text, to the second if needed
+	let seconds = Alarm::new(ClockTime::from_seconds(7 * 60 * 60 + 15), None);
+	let saved = SavedState { clock_offset: 0, alarms: vec![seconds] };
+	saved.save(&path).unwrap();
+	assert!(fs::read_to_string(&path).unwrap().contains("time = \"07:00:15\""));
+	assert_eq!(SavedState::load(&path), saved);
+
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_alarm_due() {
+	let mut alarm = Alarm::new(ClockTime::new(23 * 60 + 59), None);
+	alarm.repeat = Repeat::Weekdays;
+
+	// friday night to saturday morning, after midnight
+	assert_eq!(alarm.due_between(at(4, 23, 50), at(5, 0, 5)), Some(at(4, 23, 59)));
+	assert_eq!(alarm.due_between(at(4, 23, 59), at(5, 0, 5)), None);
+	// saturday night isn't a weekday
+	assert_eq!(alarm.due_between(at(5, 23, 50), at(6, 0, 5)), None);
+
+	alarm.last_rang = Some(at(4, 23, 59));
+	assert_eq!(alarm.due_between(at(4, 23, 50), at(5, 0, 5)), None);
+}
+
+#[test]
+fn test_ring_since() {
+	let alarm = Alarm::new(ClockTime::new(7 * 60), None);
+	let tick = chrono::Duration::milliseconds(100);
+	let no_grace = chrono::Duration::zero();
+	let grace = chrono::Duration::minutes(15);
+
+	// alarms going off as the clock runs normally ring even without a grace window
+	let since = ring_since(at(1, 7, 0) - tick, at(1, 7, 0), no_grace);
+	assert_eq!(alarm.due_between(since, at(1, 7, 0)), Some(at(1, 7, 0)));
+	let since = ring_since(at(1, 6, 59), at(1, 7, 0), no_grace);
+	assert_eq!(alarm.due_between(since, at(1, 7, 0)), Some(at(1, 7, 0)));
+
+	// but alarms jumped over only ring within the window
+	let since = ring_since(at(1, 6, 0), at(1, 7, 10), no_grace);
+	assert_eq!(alarm.due_between(since, at(1, 7, 10)), None);
+	let since = ring_since(at(1, 6, 0), at(1, 7, 10), grace);
+	assert_eq!(alarm.due_between(since, at(1, 7, 10)), Some(at(1, 7, 0)));
+	let since = ring_since(at(1, 6, 0), at(1, 7, 20), grace);
+	assert_eq!(alarm.due_between(since, at(1, 7, 20)), None);
+}
+
+#[test]
+fn test_silent_alarms() {
+	let _lock = ALARM_LOCK.lock();
+	RINGING.write().take();
+
+	// an alarm without a song due at the same time doesn't stop the others going off
+	let mut silent = Alarm::new(ClockTime::new(7 * 60), None);
+	silent.wake_minutes = 10;
+	let mut song = Alarm::new(ClockTime::new(7 * 60), Some(PathBuf::from("song.mid")));
+	song.wake_minutes = 10;
+	*ALARMS.write() = vec![silent, song];
+
+	assert_eq!(wake_song(at(1, 6, 49), at(1, 6, 50)), Some(PathBuf::from("song.mid")));
+	assert_eq!(alarm_song(at(1, 6, 59), at(1, 7, 0), chrono::Duration::zero(), at(1, 7, 0)), Some(PathBuf::from("song.mid")));
+	assert_eq!(ALARMS.read()[0].last_rang, None);
+
+	RINGING.write().take();
+	ALARMS.write().clear();
+}
+
+#[test]
+fn test_daylight_saving() {
+	let london = |month, day, hours, minutes, seconds| {
+		NaiveDate::from_ymd(2021, month, day).and_hms(hours, minutes, seconds)
+	};
+	let clock = ZonedClock::new(chrono_tz::Europe::London, chrono::Duration::zero());
+
+	// clocks go forward at 1am in march and back at 2am in october
+	assert_eq!(clock.skipped(london(3, 28, 0, 59, 59), london(3, 28, 2, 0, 0)), chrono::Duration::hours(1));
+	assert_eq!(clock.skipped(london(3, 28, 2, 0, 0), london(3, 28, 2, 0, 1)), chrono::Duration::zero());
+	assert_eq!(clock.skipped(london(10, 31, 1, 59, 59), london(10, 31, 1, 0, 0)), chrono::Duration::zero());
+
+	// times in the skipped hour are set as far past its end, repeated times to the first time round
+	clock.set(london(3, 28, 1, 30, 0));
+	assert_delta!((clock.now() - london(3, 28, 2, 30, 0)).num_milliseconds(), 0, 1000);
+	clock.set(london(10, 31, 1, 30, 0));
+	assert_delta!((clock.now() - london(10, 31, 1, 30, 0)).num_milliseconds(), 0, 1000);
+	assert_delta!((clock.offset() - (london(10, 31, 0, 30, 0) - Utc::now().naive_utc())).num_milliseconds(), 0, 1000);
+
+	// steady readings go on through the repeated hour, so a countdown of an hour ends an hour later
+	let steady = clock.steady_now();
+	assert_delta!((clock.steady_to_local(steady) - clock.now()).num_milliseconds(), 0, 1000);
+	assert_delta!((steady - london(10, 31, 0, 30, 0)).num_milliseconds(), 0, 1000);
+	assert_eq!(clock.steady_to_local(london(10, 31, 1, 30, 0)), london(10, 31, 1, 30, 0));
+
+	let _lock = ALARM_LOCK.lock();
+	RINGING.write().take();
+	*ALARMS.write() = vec![Alarm::new(ClockTime::new(60 + 30), Some(PathBuf::from("skipped.mid")))];
+
+	// an alarm in the skipped hour goes off as it is skipped, even though that's past the grace window
+	let skipped = clock.skipped(london(3, 28, 0, 59, 59), london(3, 28, 2, 0, 0));
+	assert_eq!(alarm_song(london(3, 28, 0, 59, 59), london(3, 28, 2, 0, 0), skipped, london(3, 28, 2, 0, 0)), Some(PathBuf::from("skipped.mid")));
+	RINGING.write().take();
+
+	// an alarm in the repeated hour only goes off the first time round
+	let no_skip = chrono::Duration::zero();
+	assert!(alarm_song(london(10, 31, 1, 29, 59), london(10, 31, 1, 30, 0), no_skip, london(10, 31, 1, 30, 0)).is_some());
+	RINGING.write().take();
+	assert_eq!(alarm_song(london(10, 31, 1, 59, 59), london(10, 31, 1, 0, 0), no_skip, london(10, 31, 1, 0, 0)), None);
+	assert_eq!(alarm_song(london(10, 31, 1, 29, 59), london(10, 31, 1, 30, 0), no_skip, london(10, 31, 1, 30, 0)), None);
+
+	// snoozes run for real time, so one started at 1:55 before the clocks go back ends at 1:04 after
+	let utc = london;
+	let mut snoozed = Ringing::new(PathBuf::from("skipped.mid"), Challenge::None, utc(10, 31, 0, 50, 0));
+	snoozed.snoozed_until = Some(utc(10, 31, 0, 55, 0) + chrono::Duration::minutes(9));
+	*RINGING.write() = Some(snoozed);
+	assert_eq!(alarm_song(london(10, 31, 1, 59, 59), london(10, 31, 1, 0, 0), no_skip, utc(10, 31, 1, 0, 0)), None);
+	assert!(alarm_song(london(10, 31, 1, 3, 59), london(10, 31, 1, 4, 0), no_skip, utc(10, 31, 1, 4, 0)).is_some());
+	assert_eq!(RINGING.read().as_ref().unwrap().since, utc(10, 31, 1, 4, 0));
+	RINGING.write().take();
+
+	ALARMS.write().clear();
+}
+
+#[test]
+fn test_time_format() {
+	assert_eq!(pair_chars(7, 5), ['0', '7', '0', '5']);
+	assert_eq!(pair_chars(123, 45), ['9', '9', '4', '5']);
+	assert_eq!(ClockTime::new(23 * 60 + 59).as_chars(), ['2', '3', '5', '9']);
+
+	// minutes and seconds, then hours and minutes from an hour on
+	assert_eq!(duration_chars(0), ['0', '0', '0', '0']);
+	assert_eq!(duration_chars(59 * 60 + 59), ['5', '9', '5', '9']);
+	assert_eq!(duration_chars(60 * 60), ['0', '1', '0', '0']);
+	assert_eq!(duration_chars(10 * 60 * 60 + 30 * 60 + 59), ['1', '0', '3', '0']);
+
+	// 12 hour time, where 12 comes before 1
+	assert_eq!(ClockTime::new(0).format_chars(true), ['1', '2', '0', '0']);
+	assert_eq!(ClockTime::new(7 * 60 + 30).format_chars(true), [' ', '7', '3', '0']);
+	assert_eq!(ClockTime::new(12 * 60 + 5).format_text(true), "1205 PM");
+	assert_eq!(ClockTime::new(19 * 60 + 30).format_text(true), "730 PM");
+	assert_eq!(ClockTime::new(19 * 60 + 30).format_text(false), "1930");
+	assert_eq!(ClockTime::new(0).am_pm_chars(), [' ', 'A', 'M', ' ']);
+
+	// markers are shown at the end of every few seconds
+	assert!(!marker_due(0, 10, 750));
+	assert!(marker_due(9_250, 10, 750));
+	assert!(!marker_due(10_000, 10, 750));
+	assert!(!marker_due(9_250, 0, 750));
+
+	let time = ClockTime::from_seconds((7 * 60 + 30) * 60 + 15);
+	assert_eq!((time.hours(), time.minutes(), time.seconds()), (7, 30, 15));
+	assert_eq!(ClockTime::from(NaiveTime::from(time)), time);
+	let now = NaiveDate::from_ymd(2021, 6, 1).and_time(time.into());
+	assert_eq!(ClockFace::HoursMinutes.text(now, "%a %-d %b"), "0730");
+	assert_eq!(ClockFace::MinutesSeconds.text(now, "%a %-d %b"), "3015");
+	assert_eq!(ClockFace::Seconds.text(now, "%a %-d %b"), "  15");
+	assert_eq!(ClockFace::Date.text(now, "%a %-d %b"), "Tue 1 Jun");
+
+	// faces take turns, each shown for a few seconds
+	let faces = [ClockFace::HoursMinutes, ClockFace::Seconds];
+	assert_eq!(clock_face_chars(&faces, 6, "", now, 300, 4), ['0', '7', '3', '0']);
+	assert_eq!(clock_face_chars(&faces, 10, "", now, 300, 4), [' ', ' ', '1', '5']);
+	assert_eq!(clock_face_chars(&[], 5, "", now, 300, 4), ['0', '7', '3', '0']);
+	assert_eq!(clock_face_at(&faces, 10, now), ClockFace::Seconds);
+
+	// and long faces scroll along from the start of their turn
+	assert_eq!(clock_face_chars(&[ClockFace::Date], 10, "%a %-d %b", now, 10_000, 4), ['T', 'u', 'e', ' ']);
+	assert_eq!(clock_face_chars(&[ClockFace::Date], 10, "%a %-d %b", now, 1000, 4), [' ', 'J', 'u', 'n']);
+	assert_eq!(scroll_chars("ab", 3, 4), ['a', 'b', ' ', ' ']);
+	assert_eq!(scroll_chars("Tue 1 Jun", 8, 4), ['n', ' ', ' ', ' ']);
+	assert_eq!(scroll_chars("Tue 1 Jun", 13, 4), ['T', 'u', 'e', ' ']);
+
+	// wider displays fit more before scrolling
+	assert_eq!(clock_face_chars(&[ClockFace::Date], 10, "%a %-d %b", now, 1000, 12).iter().collect::<String>(), "Tue 1 Jun   ");
+	assert_eq!(scroll_chars("Tue 1 Jun", 2, 8).iter().collect::<String>(), "e 1 Jun ");
+	assert_eq!(scroll_chars("Tue 1 Jun", 17, 8).iter().collect::<String>(), "Tue 1 Ju");
+}
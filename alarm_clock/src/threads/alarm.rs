@@ -1,30 +1,233 @@
+use std::path::{ Path, PathBuf };
+use std::sync::Arc;
 use std::sync::mpsc::Sender;
 use std::time::Duration;
 
-use crate::{ ALARM_TIME, ALARM_SONG, ClockTime };
-use crate::message::PlayerMessage;
+use chrono::NaiveDateTime;
+
+use crate::{ ALARMS, CONFIG, NAP, RINGING, SLEEP, TIMER };
+use crate::alarm::{ Challenge, Repeat, Ringing };
+use crate::clock::Clock;
+use crate::config::Escalation;
+use crate::message::{ AlarmEvent, EventMessage, PlayerMessage };
+use crate::threads::player::song_name;
 
 pub fn alarm_thread(
 	player_sender: Sender<PlayerMessage>,
-) -> rppal::i2c::Result<()> {
-	let mut before = ClockTime::now();
+	event_sender: Sender<EventMessage>,
+	clock: Arc<dyn Clock>,
+) {
+	let mut before = clock.now();
 
 	loop {
 		std::thread::sleep(Duration::from_millis(100));
 
-		let after = ClockTime::now();
+		let now = clock.now();
+		let steady_now = clock.steady_now();
+
+		if let Some(song) = alarm_song(before, now, clock.skipped(before, now), steady_now) {
+			if player_sender.send(PlayerMessage::Loop(song)).is_err() {
+				return;
+			}
+		} else if let Some((msg, event)) = escalate(steady_now) {
+			if player_sender.send(msg).is_err() || event_sender.send(event.into()).is_err() {
+				return;
+			}
+		} else if let Some(song) = wake_song(before, now) {
+			if player_sender.send(PlayerMessage::Gentle(song)).is_err() {
+				return;
+			}
+		}
+
+		if let Some(msg) = sleep_update(steady_now) {
+			if player_sender.send(msg).is_err() {
+				return;
+			}
+		}
+
+		if now.timestamp() != before.timestamp() && event_sender.send(EventMessage::Tick).is_err() {
+			return;
+		}
+
+		before = now;
+	}
+}
+
+/// Song to loop for an alarm going off as the clock moves from `before` to `now`, the countdown
+/// timer ending, a nap alarm going off, or a snoozed alarm whose snooze has run out. Either way the
+/// alarm is left ringing.
+///
+/// Alarms skipped over by the clock jumping forward or the program stalling still go off, as long as
+/// they were due no more than the configured grace window ago. Local time `skipped` by daylight saving
+/// time is added to the window, so alarms in a skipped hour go off once it is skipped. Alarms in a
+/// repeated hour only go off the first time round. A timer or nap ending while another alarm is
+/// ringing or snoozed waits until that alarm is dismissed. Timers, naps and snoozes end by
+/// `steady_now`, so daylight saving time doesn't move them.
+pub fn alarm_song(
+	before: NaiveDateTime,
+	now: NaiveDateTime,
+	skipped: chrono::Duration,
+	steady_now: NaiveDateTime,
+) -> Option<PathBuf> {
+	let grace = chrono::Duration::minutes(CONFIG.read().alarm_grace_minutes.into()) + skipped;
+	let since = ring_since(before, now, grace);
+	let busy = RINGING.read().is_some();
 
-		if let Some(alarm_time) = *ALARM_TIME.read() {
-			if before != alarm_time && after == alarm_time {
-				if let Some(song) = &*ALARM_SONG.read() {
-					player_sender.send(PlayerMessage::Loop(song.clone()))
-						.expect("Unable to play alarm song");
-				}
+	// only the first alarm is played if several go off at once, and alarms without a song are left
+	// for the others
+	let song = ALARMS.write().iter_mut()
+		.filter(|alarm| alarm.song.is_some())
+		.find_map(|alarm| Some((alarm.due_between(since, now)?, alarm)))
+		.and_then(|(due, alarm)| {
+			alarm.last_rang = Some(due);
+
+			if alarm.repeat == Repeat::Once {
+				alarm.enabled = false;
+			}
+
+			Some((alarm.song.clone()?, alarm.challenge.unwrap_or(CONFIG.read().dismiss_challenge)))
+		})
+		.or_else(|| {
+			let mut timer = TIMER.write();
+
+			if busy || timer.as_ref()?.ends_at > steady_now {
+				return None;
 			}
+
+			Some((timer.take()?.song, Challenge::None))
+		})
+		.or_else(|| {
+			// naps are only set for one time, so they cancel themselves once they go off
+			let mut nap = NAP.write();
+
+			if busy || nap.as_ref()?.ends_at > steady_now {
+				return None;
+			}
+
+			Some((nap.take()?.song, CONFIG.read().dismiss_challenge))
+		});
+
+	let mut ringing = RINGING.write();
+
+	match (song, &mut *ringing) {
+		(Some((song, challenge)), ringing) => {
+			*ringing = Some(Ringing::new(song.clone(), challenge, steady_now));
+
+			Some(song)
 		}
+		(None, Some(snoozed)) if snoozed.snoozed_until.is_some_and(|until| until <= steady_now) => {
+			snoozed.snoozed_until = None;
+			snoozed.since = steady_now;
 
-		before = after;
+			Some(snoozed.song.clone())
+		}
+		(None, _) => None,
 	}
 }
 
+/// Longest the clock can move between two checks and still count as running normally, rather than
+/// jumping forward or the program stalling.
+const MAX_STEP_SECONDS: i64 = 60;
+
+/// Start of the window of alarms to ring as the clock moves from `before` to `now`. That's every
+/// alarm since `before` while the clock runs normally, but after a jump only those due less than
+/// `grace` ago.
+pub fn ring_since(before: NaiveDateTime, now: NaiveDateTime, grace: chrono::Duration) -> NaiveDateTime {
+	if now - before <= chrono::Duration::seconds(MAX_STEP_SECONDS) {
+		before
+	} else {
+		before.max(now - grace)
+	}
+}
 
+/// Song to play gently for an alarm whose gentle wake starts as the clock moves from `before` to
+/// `now`. Nothing starts while another alarm is ringing, or once the alarm itself is due.
+pub fn wake_song(before: NaiveDateTime, now: NaiveDateTime) -> Option<PathBuf> {
+	if RINGING.read().is_some() {
+		return None;
+	}
+
+	ALARMS.read().iter()
+		.filter(|alarm| alarm.wake_minutes > 0 && alarm.song.is_some())
+		.find(|alarm| {
+			let wake = chrono::Duration::minutes(alarm.wake_minutes.into());
+			alarm.due_between(before + wake, now + wake).is_some_and(|due| due > now)
+		})
+		.and_then(|alarm| alarm.song.clone())
+}
+
+/// Take the next escalation step from the config once the ringing alarm has rung long enough,
+/// returning what to send to the player and the event raised. Steps are timed by `steady_now`, so
+/// daylight saving time doesn't move them.
+pub fn escalate(steady_now: NaiveDateTime) -> Option<(PlayerMessage, AlarmEvent)> {
+	let config = CONFIG.read();
+
+	escalate_steps(steady_now, &config.escalation, Path::new(config.midi_dir()))
+}
+
+/// Take the next of `steps` once the ringing alarm has rung long enough, with songs found in
+/// `midi_dir`.
+pub fn escalate_steps(steady_now: NaiveDateTime, steps: &[Escalation], midi_dir: &Path) -> Option<(PlayerMessage, AlarmEvent)> {
+	let mut ringing_guard = RINGING.write();
+	let ringing = ringing_guard.as_mut().filter(|ringing| ringing.snoozed_until.is_none())?;
+
+	let step = steps.get(ringing.escalations)
+		.filter(|step| steady_now >= ringing.since + chrono::Duration::minutes(step.after_minutes().into()))?;
+
+	ringing.escalations += 1;
+
+	match step {
+		Escalation::Song { song, .. } => {
+			ringing.song = midi_dir.join(song);
+
+			Some((PlayerMessage::Loop(ringing.song.clone()), AlarmEvent::Escalate(song_name(&ringing.song))))
+		}
+		Escalation::Stop { .. } => {
+			*ringing_guard = None;
+
+			Some((PlayerMessage::Stop, AlarmEvent::Stop))
+		}
+	}
+}
+
+/// Stop the player once the sleep timer ends, slowing it down over the last few minutes before then.
+///
+/// An alarm going off takes over the player, so it cancels the sleep timer. The sleep timer ends by
+/// `steady_now`, so daylight saving time doesn't move it.
+pub fn sleep_update(steady_now: NaiveDateTime) -> Option<PlayerMessage> {
+	let ringing = RINGING.read().is_some();
+	let mut sleep = SLEEP.write();
+	let timer = sleep.as_mut()?;
+
+	if ringing {
+		let speed = timer.speed;
+		*sleep = None;
+
+		return (speed != 1.0).then_some(PlayerMessage::Speed(1.0));
+	} else if steady_now >= timer.ends_at {
+		*sleep = None;
+
+		return Some(PlayerMessage::Stop);
+	}
+
+	let config = CONFIG.read();
+	let slowdown = chrono::Duration::minutes(config.sleep_slowdown_minutes.into());
+	let left = timer.ends_at - steady_now;
+
+	if left >= slowdown {
+		return None;
+	}
+
+	// slows down evenly, in steps of 5%
+	let progress = 1.0 - left.num_milliseconds() as f64 / slowdown.num_milliseconds() as f64;
+	let speed = 1.0 - progress * (1.0 - config.sleep_slowest_speed);
+	let speed = (speed * 20.0).round() / 20.0;
+
+	if speed != timer.speed {
+		timer.speed = speed;
+
+		Some(PlayerMessage::Speed(speed))
+	} else {
+		None
+	}
+}
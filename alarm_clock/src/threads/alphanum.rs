@@ -1,67 +1,140 @@
 use std::convert::TryInto;
-use std::sync::mpsc;
-use std::time::Duration;
+use std::sync::{ mpsc, Arc };
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{ Duration, Instant };
 
-use crate::{ CONFIG, ClockTime };
+use chrono::NaiveDateTime;
 
-use crate::circuit::Alphanum;
-use crate::message::AlphanumMessage;
+use crate::CONFIG;
 
-const BLANKS: [char; 4] = [' ', ' ', ' ', ' '];
+use crate::circuit::{ glyphs, AlphanumDisplay, Glyph };
+use crate::clock::Clock;
+use crate::time_format::{ clock_face_at, clock_face_chars, day_ms, duration_chars, marker_due, AmPmMarker, ClockFace };
+use crate::message::AlphanumMessage;
 
-enum TextMode<I: Iterator<Item = char>> {
+enum TextMode<I: Iterator<Item = Glyph>> {
 	Time,
-	Static([char; 4]),
+	Countdown(NaiveDateTime),
+	Elapsed(NaiveDateTime),
+	Static,
+	Marked(String, String, Instant),
 	Iter(I),
 }
 
-pub fn alphanum_thread(
-	mut alphanum: Alphanum,
+pub fn alphanum_thread<A: AlphanumDisplay>(
+	mut alphanum: A,
 	receiver: mpsc::Receiver<AlphanumMessage>,
-) -> rppal::i2c::Result<()> {
+	clock: Arc<dyn Clock>,
+) -> Result<(), A::Error> {
 	let mut text; // must keep text in scope to create an iterator
 	let mut text_mode = TextMode::Time;
+	let width = alphanum.width();
 
 	loop {
 		let msg = receiver.recv_timeout(Duration::from_millis(CONFIG.read().scroll_delay_ms));
 
 		match msg {
 			Ok(msg) => match msg {
-				AlphanumMessage::Static(chars) => {
-					alphanum.display(&chars)?;
-					text_mode = TextMode::Static(chars);
+				AlphanumMessage::Static(text) => {
+					alphanum.display(&text.chars().collect::<Vec<_>>())?;
+					text_mode = TextMode::Static;
+				}
+				AlphanumMessage::Glyphs(glyphs) => {
+					alphanum.display_glyphs(&glyphs)?;
+					text_mode = TextMode::Static;
 				}
 				AlphanumMessage::Loop(t) => {
-					text = t + "    ";
+					text = glyphs(&(t + &" ".repeat(width)));
 					let offset = CONFIG.read().text_offset.rem_euclid(
-						text.len().try_into().unwrap_or(i8::MAX)
+						text.len().try_into().unwrap_or(i8::MAX).max(1)
 					).try_into().unwrap();
-					text_mode = TextMode::Iter(text.chars().cycle().skip(offset));
+					text_mode = TextMode::Iter(text.iter().copied().cycle().skip(offset));
+				}
+				AlphanumMessage::Marked(chars, marker) => {
+					text_mode = TextMode::Marked(chars, marker, Instant::now());
 				}
 				AlphanumMessage::Time => {
 					text_mode = TextMode::Time;
 				}
+				AlphanumMessage::Countdown(end) => {
+					text_mode = TextMode::Countdown(end);
+				}
+				AlphanumMessage::Elapsed(start) => {
+					text_mode = TextMode::Elapsed(start);
+				}
 				AlphanumMessage::Empty => {
-					text_mode = TextMode::Static(BLANKS);
-					alphanum.display(&BLANKS)?;
+					text_mode = TextMode::Static;
+					alphanum.display(&[])?;
 				}
 				AlphanumMessage::Blink(blink_rate) => {
 					alphanum.blink_rate(blink_rate)?;
 				}
 			}
-			Err(_) => (),
+			Err(RecvTimeoutError::Timeout) => (),
+			Err(RecvTimeoutError::Disconnected) => return Ok(()),
 		}
 
 		match text_mode {
 			TextMode::Time => {
-				let clock_time = ClockTime::now();
+				let config = CONFIG.read();
+				let now = clock.now();
+				let ms = day_ms(now);
+				let am_pm_text = config.twelve_hour && config.am_pm_marker == AmPmMarker::Text;
+
+				let glyphs = if am_pm_text && marker_due(ms, config.am_pm_every_seconds, config.am_pm_ms) {
+					clock.clock_time().am_pm_chars().iter().copied().map(Glyph::from).collect()
+				} else {
+					let face = clock_face_at(&config.clock_faces, config.clock_face_seconds, now);
+					let mut glyphs = clock_face_chars(
+						&config.clock_faces,
+						config.clock_face_seconds,
+						&config.date_format,
+						now,
+						config.scroll_delay_ms,
+						width,
+					).into_iter().map(Glyph::from).collect::<Vec<_>>();
+
+					if let Some(glyph) = glyphs.get_mut(1) {
+						glyph.dot = face.dot_lit(config.time_separator, ms);
+					}
+
+					if face == ClockFace::HoursMinutes && config.twelve_hour && config.am_pm_marker == AmPmMarker::Dot {
+						if let Some(glyph) = glyphs.get_mut(3) {
+							glyph.dot = clock.clock_time().is_pm();
+						}
+					}
+
+					glyphs
+				};
+				drop(config);
+
+				alphanum.display_glyphs(&glyphs)?;
+			}
+			TextMode::Countdown(end) => {
+				// rounded up, so zero is only shown once the countdown has ended
+				let millis = (end - clock.steady_now()).num_milliseconds().max(0) as u64;
+
+				alphanum.display(&duration_chars(millis.div_ceil(1000)))?;
+			}
+			TextMode::Elapsed(start) => {
+				let seconds = (clock.steady_now() - start).num_seconds().max(0) as u64;
+
+				alphanum.display(&duration_chars(seconds))?;
+			}
+			TextMode::Static => (),
+			TextMode::Marked(ref text, ref marker, since) => {
+				let elapsed = since.elapsed().as_millis() as u64;
+				let due = {
+					let config = CONFIG.read();
+					marker_due(elapsed, config.am_pm_every_seconds, config.am_pm_ms)
+				};
 
-				alphanum.display(&clock_time.as_chars())?;
+				let shown = if due { marker } else { text };
+				alphanum.display(&shown.chars().collect::<Vec<_>>())?;
 			}
-			TextMode::Static(_) => (),
 			TextMode::Iter(ref mut iter) => {
-				let chars = iter.clone().take(4).collect::<Vec<_>>().try_into().unwrap();
-				alphanum.display(&chars)?;
+				let glyphs = iter.clone().take(width).collect::<Vec<_>>();
+				alphanum.display_glyphs(&glyphs)?;
 				iter.next();
 			}
 		}
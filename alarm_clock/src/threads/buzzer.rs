@@ -2,11 +2,14 @@ use std::sync::mpsc;
 use std::sync::mpsc::TryRecvError::*;
 use std::time;
 
-use crate::circuit::Buzzer;
+use crate::circuit::ToneOutput;
 use crate::message::BuzzerMessage;
 use crate::note::MidiNote;
 
-pub fn update_buzzer(note_receiver: mpsc::Receiver<BuzzerMessage>, mut buzzer: Buzzer<MidiNote>) -> f64 {
+pub fn update_buzzer(
+	note_receiver: mpsc::Receiver<BuzzerMessage>,
+	mut buzzer: impl ToneOutput<MidiNote>,
+) -> f64 {
 	let start = time::Instant::now();
 	let mut updates: u64 = 0;
 
@@ -2,62 +2,82 @@ use std::collections::BTreeMap;
 use std::sync::mpsc;
 use std::time::{ Duration, Instant };
 
-use rppal::gpio::{ Gpio, Level };
-
 use crate::CONFIG;
-use crate::circuit::Button;
+use crate::circuit::InputSource;
 use crate::message::{ EventMessage, ButtonEvent };
 
 struct Input {
-	input_type: InputType,
 	bounce_time: Duration,
 	last_event: Option<Instant>,
+	/// Last event sent on, which is the level the button is taken to be at.
+	sent: Option<ButtonEvent>,
+	/// Latest event within the bounce time, sent once the bounce time is up if it changes the level.
+	pending: Option<ButtonEvent>,
 }
 
 impl Input {
 	fn new(input_type: InputType) -> Self {
 		let bounce_time = match input_type {
-			InputType::Button(_) => Duration::from_millis(CONFIG.read().button_bounce_ms()),
+			InputType::Button => Duration::from_millis(CONFIG.read().button_bounce_ms()),
 		};
 
-		Self { input_type, bounce_time, last_event: None }
+		Self { bounce_time, last_event: None, sent: None, pending: None }
+	}
+
+	/// When the pending event is due to be sent, if there is one.
+	fn settles_at(&self) -> Option<Instant> {
+		self.pending.and(self.last_event).map(|e| e + self.bounce_time)
+	}
+
+	/// Latest event once the bounce time since the last one sent is up, unless it leaves the button
+	/// where it was. Events aren't dropped for bouncing, so a release is never lost.
+	fn settled(&mut self, now: Instant) -> Option<ButtonEvent> {
+		if self.last_event.is_some_and(|e| now < e + self.bounce_time) {
+			return None;
+		}
+
+		let event = self.pending.take().filter(|&event| Some(event) != self.sent)?;
+
+		self.sent = Some(event);
+		self.last_event = Some(now);
+
+		Some(event)
 	}
 }
 
 enum InputType {
-	Button(u8),
+	Button,
 }
 
-pub fn poll_inputs(
+pub fn poll_inputs<I: InputSource>(
 	event_sender: mpsc::Sender<EventMessage>,
-	buttons: Vec<Button>,
-) -> rppal::gpio::Result<()> {
-	let gpio = Gpio::new()?;
+	mut input_source: I,
+) -> Result<(), I::Error> {
+	let mut inputs = BTreeMap::new();
 
-	let mut inputs: BTreeMap<_,_> = buttons.iter()
-		.enumerate()
-		.map(|(i, button)| (
-			button.input_pin().pin(),
-			Input::new(InputType::Button(i as u8))
-		)).collect();
+	loop {
+		let timeout = inputs.values()
+			.filter_map(Input::settles_at)
+			.min()
+			.map(|at| at.saturating_duration_since(Instant::now()));
 
-	let pins: Vec<_> = buttons.iter()
-		.map(|button| button.input_pin())
-		.collect();
+		if let Some(event) = input_source.poll(timeout)? {
+			let i = match event {
+				ButtonEvent::Press(i) | ButtonEvent::Release(i) => i,
+			};
 
-	loop {
-		if let Some((pin, level)) = gpio.poll_interrupts(&pins, false, None)? {
-			let mut input = inputs.get_mut(&pin.pin()).unwrap();
-
-			if input.last_event.map_or(true, |e| e + input.bounce_time <= Instant::now()) {
-				match (&input.input_type, level) {
-					(InputType::Button(i), Level::High)
-						=> event_sender.send(ButtonEvent::Release(*i).into()),
-					(InputType::Button(i), Level::Low)
-						=> event_sender.send(ButtonEvent::Press(*i).into()),
-				}.unwrap();
-
-				input.last_event = Some(Instant::now());
+			inputs.entry(i)
+				.or_insert_with(|| Input::new(InputType::Button))
+				.pending = Some(event);
+		}
+
+		let now = Instant::now();
+
+		for input in inputs.values_mut() {
+			if let Some(event) = input.settled(now) {
+				if event_sender.send(event.into()).is_err() {
+					return Ok(());
+				}
 			}
 		}
 	}
@@ -3,3 +3,4 @@ pub mod buzzer;
 pub mod player;
 pub mod alphanum;
 pub mod alarm;
+pub mod save;
@@ -5,6 +5,7 @@ use std::sync::mpsc;
 use std::sync::mpsc::TryRecvError::{Disconnected, Empty};
 use std::time;
 
+use crate::CONFIG;
 use crate::message::{BuzzerMessage, PlayerMessage, EventMessage, SongEvent};
 
 use crate::note::MidiNote;
@@ -22,21 +23,31 @@ pub fn midi_player(
 ) {
 	let mut playing_name = None;
 	let mut looping = false;
+	let mut speed = 1.0;
+	let mut gentle = false;
 
 	loop {
 		// stopped loop
-		while playing_name == None {
+		while playing_name.is_none() {
 			match player_receiver.recv() {
 				Ok(message) => match message {
 					PlayerMessage::Loop(name) => {
 						playing_name = Some(name);
 						looping = true;
+						gentle = false;
 					}
 					PlayerMessage::Play(name) => {
 						playing_name = Some(name);
 						looping = false;
+						gentle = false;
 					}
-					PlayerMessage::Stop => (),
+					PlayerMessage::Gentle(name) => {
+						playing_name = Some(name);
+						looping = true;
+						gentle = true;
+					}
+					PlayerMessage::Stop => speed = 1.0,
+					PlayerMessage::Speed(new_speed) => speed = new_speed,
 				}
 				Err(_) => return,
 			}
@@ -52,6 +63,13 @@ pub fn midi_player(
 			let tracks = &smf.tracks;
 
 			let mut tempo = 500_000; // microseconds per beat
+			let (speed_scale, max_notes) = if gentle {
+				let config = CONFIG.read();
+				(config.wake_speed, config.wake_max_notes)
+			} else {
+				(1.0, usize::MAX)
+			};
+			let mut notes = Notes::new(max_notes);
 			let ticks_per_beat =
 				if let Timing::Metrical(tpb) = smf.header.timing {
 					tpb.as_int()
@@ -66,7 +84,7 @@ pub fn midi_player(
 				let mut events_i = track.iter().peekable();
 				let next_time_ms = match events_i.peek() {
 					Some(ev) => delta_to_micros(
-						ticks_per_beat, tempo, ev.delta.as_int()
+						ticks_per_beat, scale_tempo(tempo, speed * speed_scale), ev.delta.as_int()
 					),
 					None => 0,
 				};
@@ -93,19 +111,30 @@ pub fn midi_player(
 							event_sender.send(SongEvent::End(song_name(some_name)).into()).unwrap();
 							playing_name = Some(name);
 							looping = true;
+							gentle = false;
 							break;
 						}
 						PlayerMessage::Play(name) => {
 							event_sender.send(SongEvent::End(song_name(some_name)).into()).unwrap();
 							playing_name = Some(name);
 							looping = false;
+							gentle = false;
+							break;
+						}
+						PlayerMessage::Gentle(name) => {
+							event_sender.send(SongEvent::End(song_name(some_name)).into()).unwrap();
+							playing_name = Some(name);
+							looping = true;
+							gentle = true;
 							break;
 						}
 						PlayerMessage::Stop => {
 							event_sender.send(SongEvent::End(song_name(some_name)).into()).unwrap();
 							playing_name = None;
+							speed = 1.0;
 							break;
 						}
+						PlayerMessage::Speed(new_speed) => speed = new_speed,
 					}
 					Err(e) => match e {
 						Empty => (),
@@ -127,7 +156,9 @@ pub fn midi_player(
 						match event.kind {
 							EventKind::Midi { channel: _, message } => {
 								if let Some((on, note)) = midi_to_buzzer(message) {
-									note_sender.send(BuzzerMessage::Note{on, note}).unwrap();
+									for msg in notes.update(on, note) {
+										note_sender.send(msg).unwrap();
+									}
 								}
 							}
 							EventKind::Meta(MetaMessage::Tempo(new_tempo)) => {
@@ -139,7 +170,7 @@ pub fn midi_player(
 						if let Some(next_event) = events[ti].peek() {
 							next_times[ti] += time::Duration::from_micros(
 								delta_to_micros(
-									ticks_per_beat, tempo, next_event.delta.as_int()
+									ticks_per_beat, scale_tempo(tempo, speed * speed_scale), next_event.delta.as_int()
 								)
 							);
 						};
@@ -152,10 +183,112 @@ pub fn midi_player(
 	}
 }
 
+/// Buzzer messages for playing a song once at normal speed, each with how long after the start of
+/// the song it is sent. The song is walked in virtual time rather than played, so this takes no
+/// longer than reading it and gives the same messages every time.
+pub fn song_messages(smf: &Smf) -> Vec<(time::Duration, BuzzerMessage)> {
+	let ticks_per_beat =
+		if let Timing::Metrical(tpb) = smf.header.timing {
+			tpb.as_int()
+		} else {
+			panic!("Currently only supports metrical time")
+		};
+
+	let mut tempo = 500_000; // microseconds per beat
+	let mut notes = Notes::new(usize::MAX);
+	let mut events = smf.tracks.iter().map(|track| track.iter().peekable()).collect::<Vec<_>>();
+	let mut next_times = events.iter_mut()
+		.map(|events_i| events_i.peek().map_or(0, |ev| delta_to_micros(ticks_per_beat, tempo, ev.delta.as_int())))
+		.collect::<Vec<_>>();
+	let mut messages = Vec::new();
+	let mut now = 0;
+
+	// like the player, events due at the same time are taken in track order
+	while let Some(ti) = (0..events.len())
+		.filter(|&ti| events[ti].peek().is_some())
+		.min_by_key(|&ti| next_times[ti])
+	{
+		let event = events[ti].next().unwrap();
+		now = next_times[ti];
+
+		match event.kind {
+			EventKind::Midi { channel: _, message } => {
+				if let Some((on, note)) = midi_to_buzzer(message) {
+					for msg in notes.update(on, note) {
+						messages.push((time::Duration::from_micros(now), msg));
+					}
+				}
+			}
+			EventKind::Meta(MetaMessage::Tempo(new_tempo)) => {
+				tempo = new_tempo.as_int()
+			}
+			_ => (),
+		};
+
+		if let Some(next_event) = events[ti].peek() {
+			next_times[ti] += delta_to_micros(ticks_per_beat, tempo, next_event.delta.as_int());
+		}
+	}
+
+	messages.push((time::Duration::from_micros(now), BuzzerMessage::Clear));
+
+	messages
+}
+
 fn delta_to_micros(ticks_per_beat: u16, tempo: u32, delta: u32) -> u64 {
 	tempo as u64 * delta as u64 / ticks_per_beat as u64
 }
 
+/// Microseconds per beat when playing at the given speed, where 1 is normal speed.
+fn scale_tempo(tempo: u32, speed: f64) -> u32 {
+	(tempo as f64 / speed) as u32
+}
+
+/// Notes sounding on the buzzer, limited to the highest few when playing gently.
+struct Notes {
+	sounding: Vec<MidiNote>,
+	max: usize,
+}
+
+impl Notes {
+	fn new(max: usize) -> Self {
+		Self { sounding: Vec::new(), max }
+	}
+
+	/// Messages for the buzzer when a note in the song turns on or off.
+	fn update(&mut self, on: bool, note: MidiNote) -> Vec<BuzzerMessage> {
+		let mut msgs = Vec::new();
+
+		if !on {
+			if let Some(i) = self.sounding.iter().position(|&n| n == note) {
+				self.sounding.remove(i);
+				msgs.push(BuzzerMessage::Note { on: false, note });
+			}
+
+			return msgs;
+		}
+
+		if self.max == 0 {
+			return msgs;
+		} else if self.sounding.len() >= self.max {
+			// the melody is usually the highest note, so lower ones make way for it
+			let lowest = *self.sounding.iter().min().unwrap();
+
+			if note <= lowest {
+				return msgs;
+			}
+
+			self.sounding.retain(|&n| n != lowest);
+			msgs.push(BuzzerMessage::Note { on: false, note: lowest });
+		}
+
+		self.sounding.push(note);
+		msgs.push(BuzzerMessage::Note { on: true, note });
+
+		msgs
+	}
+}
+
 fn midi_to_buzzer(msg: MidiMessage) -> Option<(bool, MidiNote)> {
 	match msg {
 		MidiMessage::NoteOff { key, .. } => Some((
@@ -174,6 +307,6 @@ fn midi_to_buzzer(msg: MidiMessage) -> Option<(bool, MidiNote)> {
 	}
 }
 
-fn song_name(path: &Path) -> String {
+pub fn song_name(path: &Path) -> String {
 	path.file_stem().map_or("".to_owned(), |s| s.to_string_lossy().into_owned())
 }
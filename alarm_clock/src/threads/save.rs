@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::saved::SavedState;
+
+/// Write the state file whenever the alarms or clock settings differ from what was last saved.
+pub fn save_thread(
+	path: PathBuf,
+	mut saved: SavedState,
+	clock: Arc<dyn Clock>,
+) {
+	loop {
+		std::thread::sleep(Duration::from_secs(1));
+
+		let current = SavedState::current(&*clock);
+
+		if current != saved {
+			if let Err(e) = current.save(&path) {
+				eprintln!("Unable to save state file {:?}: {}", path, e);
+			}
+
+			saved = current;
+		}
+	}
+}
@@ -0,0 +1,166 @@
+//! Times and lengths of time as characters of the display.
+
+use std::convert::TryInto;
+
+use chrono::{ NaiveDateTime, Timelike };
+use serde::Deserialize;
+
+use crate::ClockTime;
+use crate::circuit::Glyph;
+
+/// Two numbers of two digits each, like hours and minutes. Numbers over 99 show as 99.
+pub fn pair_chars(high: u64, low: u64) -> [char; 4] {
+	format!("{:02}{:02}", high.min(99), low.min(99))
+		.chars()
+		.collect::<Vec<_>>()
+		.try_into()
+		.unwrap()
+}
+
+/// A length of time in minutes and seconds, or in hours and minutes once it reaches an hour.
+pub fn duration_chars(seconds: u64) -> [char; 4] {
+	if seconds < 60 * 60 {
+		pair_chars(seconds / 60, seconds % 60)
+	} else {
+		pair_chars(seconds / (60 * 60), seconds / 60 % 60)
+	}
+}
+
+/// What the clock face shows. Several can be chosen in the config to show in turn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockFace {
+	/// Hours and minutes, like 0730.
+	HoursMinutes,
+	/// Minutes and seconds, like 3015.
+	MinutesSeconds,
+	/// Seconds on their own after a lit decimal point, like "  .15".
+	Seconds,
+	/// The date in the configured format, scrolled if it is longer than the display.
+	Date,
+}
+
+impl ClockFace {
+	/// Whether the decimal point of the second character is lit `ms` milliseconds into the day. It is
+	/// the separator between two pairs of digits, and always marks the seconds face.
+	pub fn dot_lit(&self, separator: Separator, ms: u64) -> bool {
+		match self {
+			ClockFace::HoursMinutes | ClockFace::MinutesSeconds => separator.lit(ms),
+			ClockFace::Seconds => true,
+			ClockFace::Date => false,
+		}
+	}
+
+	pub fn text(&self, now: NaiveDateTime, date_format: &str) -> String {
+		let time = ClockTime::from(now.time());
+
+		let chars = match self {
+			ClockFace::HoursMinutes => time.as_chars(),
+			ClockFace::MinutesSeconds => pair_chars(time.minutes().into(), time.seconds().into()),
+			ClockFace::Seconds => {
+				let [_, _, tens, ones] = pair_chars(0, time.seconds().into());
+				[' ', ' ', tens, ones]
+			}
+			ClockFace::Date => return now.format(date_format).to_string(),
+		};
+
+		chars.iter().collect()
+	}
+}
+
+/// The clock face at `now` on a display `width` characters wide, with the faces taking turns to be
+/// shown for `face_seconds` each. Faces too wide for the display scroll along every
+/// `scroll_delay_ms` from the start of their turn.
+pub fn clock_face_chars(
+	faces: &[ClockFace],
+	face_seconds: u32,
+	date_format: &str,
+	now: NaiveDateTime,
+	scroll_delay_ms: u64,
+	width: usize,
+) -> Vec<char> {
+	let face_ms = u64::from(face_seconds.max(1)) * 1000;
+	let face = clock_face_at(faces, face_seconds, now);
+
+	scroll_chars(&face.text(now, date_format), day_ms(now) % face_ms / scroll_delay_ms.max(1), width)
+}
+
+/// The clock face whose turn it is at `now`.
+pub fn clock_face_at(faces: &[ClockFace], face_seconds: u32, now: NaiveDateTime) -> ClockFace {
+	let face_ms = u64::from(face_seconds.max(1)) * 1000;
+
+	match faces.len() {
+		0 => ClockFace::HoursMinutes,
+		len => faces[(day_ms(now) / face_ms) as usize % len],
+	}
+}
+
+/// Milliseconds since midnight.
+pub fn day_ms(now: NaiveDateTime) -> u64 {
+	u64::from(now.num_seconds_from_midnight()) * 1000 + u64::from(now.nanosecond() / 1_000_000)
+}
+
+/// Decimal point lit between the pairs of digits of a clock face, like the colon of a digital clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Separator {
+	None,
+	Dot,
+	/// Lit for the first half of every second.
+	Blink,
+}
+
+impl Separator {
+	/// Whether the separator is lit `ms` milliseconds into the day.
+	pub fn lit(&self, ms: u64) -> bool {
+		match self {
+			Separator::None => false,
+			Separator::Dot => true,
+			Separator::Blink => ms % 1000 < 500,
+		}
+	}
+}
+
+/// How 12 hour times show whether they are AM or PM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AmPmMarker {
+	/// AM or PM shown briefly in place of the time now and then.
+	Text,
+	/// The last decimal point lit for PM.
+	Dot,
+}
+
+/// Four characters of a time, with the last decimal point lit if it is PM.
+pub fn pm_dot_glyphs(chars: [char; 4], pm: bool) -> [Glyph; 4] {
+	let mut glyphs = chars.map(Glyph::from);
+	glyphs[3].dot = pm;
+
+	glyphs
+}
+
+/// `width` characters of text, moved along `step` characters and wrapping round if it doesn't fit.
+pub fn scroll_chars(text: &str, step: u64, width: usize) -> Vec<char> {
+	let chars = text.chars().collect::<Vec<_>>();
+
+	if chars.len() <= width {
+		chars.into_iter().chain(std::iter::repeat(' ')).take(width).collect()
+	} else {
+		let len = chars.len() + width;
+
+		chars.into_iter()
+			.chain(std::iter::repeat_n(' ', width))
+			.cycle()
+			.skip((step % len as u64) as usize)
+			.take(width)
+			.collect()
+	}
+}
+
+/// Whether a marker like AM or PM shown now and then is due, `elapsed_ms` into showing a time. The
+/// marker is shown for the last `marker_ms` of every `every_seconds`.
+pub fn marker_due(elapsed_ms: u64, every_seconds: u32, marker_ms: u64) -> bool {
+	let every_ms = u64::from(every_seconds) * 1000;
+
+	every_ms > 0 && elapsed_ms % every_ms >= every_ms.saturating_sub(marker_ms)
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::io::{ self, Write };
+use std::time::Duration;
+
+use crate::message::BuzzerMessage;
+use crate::note::{ MidiNote, Note };
+
+pub const SAMPLE_RATE: u32 = 44_100;
+
+const PULSE_AMPLITUDE: i16 = i16::MAX / 2;
+
+/// Renders the pulses the buzzer would make into 16 bit mono PCM samples.
+///
+/// Like `Buzzer::update`, each sounding note schedules a pulse one period after its last one,
+/// starting one period after the note is added, and any number of notes pulsing in the same sample
+/// make a single pulse. Each pulse is one sample long.
+pub struct WavRenderer {
+	notes: HashMap<MidiNote, f64>,
+	samples: Vec<i16>,
+}
+
+impl WavRenderer {
+	pub fn new() -> Self {
+		Self { notes: HashMap::new(), samples: Vec::new() }
+	}
+
+	fn time(&self) -> f64 {
+		self.samples.len() as f64 / SAMPLE_RATE as f64
+	}
+
+	/// Render messages, each applied at its time since the start of the recording.
+	pub fn render_messages<I: IntoIterator<Item = (Duration, BuzzerMessage)>>(messages: I) -> Self {
+		let mut renderer = Self::new();
+
+		for (time, msg) in messages {
+			renderer.render_until(time);
+			renderer.apply(msg);
+		}
+
+		renderer
+	}
+
+	/// Render samples up to the given time since the start of the recording.
+	pub fn render_until(&mut self, time: Duration) {
+		let end = (time.as_secs_f64() * SAMPLE_RATE as f64) as usize;
+
+		while self.samples.len() < end {
+			let now = self.time();
+			let mut play = false;
+
+			for (note, next_pulse) in &mut self.notes {
+				if *next_pulse <= now {
+					*next_pulse += 1.0 / note.frequency();
+					play = true;
+				}
+			}
+
+			self.samples.push(if play { PULSE_AMPLITUDE } else { 0 });
+		}
+	}
+
+	/// Apply a message at the current end of the recording.
+	pub fn apply(&mut self, msg: BuzzerMessage) {
+		match msg {
+			BuzzerMessage::Note { on: true, note } => {
+				let next_pulse = self.time() + 1.0 / note.frequency();
+				self.notes.insert(note, next_pulse);
+			}
+			BuzzerMessage::Note { on: false, note } => {
+				self.notes.remove(&note);
+			}
+			BuzzerMessage::Clear => self.notes.clear(),
+		}
+	}
+
+	/// Write everything rendered so far as a WAV file.
+	pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+		let data_len = (self.samples.len() * 2) as u32;
+
+		writer.write_all(b"RIFF")?;
+		writer.write_all(&(36 + data_len).to_le_bytes())?;
+		writer.write_all(b"WAVE")?;
+
+		writer.write_all(b"fmt ")?;
+		writer.write_all(&16_u32.to_le_bytes())?; // format chunk size
+		writer.write_all(&1_u16.to_le_bytes())?; // PCM
+		writer.write_all(&1_u16.to_le_bytes())?; // channels
+		writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+		writer.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?; // bytes per second
+		writer.write_all(&2_u16.to_le_bytes())?; // bytes per sample frame
+		writer.write_all(&16_u16.to_le_bytes())?; // bits per sample
+
+		writer.write_all(b"data")?;
+		writer.write_all(&data_len.to_le_bytes())?;
+
+		for sample in &self.samples {
+			writer.write_all(&sample.to_le_bytes())?;
+		}
+
+		writer.flush()
+	}
+}
@@ -25,7 +25,7 @@ pub fn freq_table(items: TokenStream) -> TokenStream {
 		_ => panic!("Invalid table identifier"),
 	};
 
-	let size: usize = std::u8::MAX as usize + 1;
+	let size: usize = u8::MAX as usize + 1;
 
 	array( name, "f64", size,
 		|i| TokenTree::from(Literal::f64_unsuffixed(A0 * 2_f64.powf( ( (i as i8) as f64 - 21.0) / 12.0 )))
@@ -67,10 +67,10 @@ fn array(name: Ident, typ: &str, size: usize, arr_value: impl Fn(usize) -> Token
 
 	tokens.push(array_type(typ, size));
 	tokens.push(Punct::new('=', Spacing::Alone).into());
-	tokens.push(array_value(size, |i| arr_value(i)));
+	tokens.push(array_value(size, arr_value));
 	tokens.push(Punct::new(';', Spacing::Alone).into());
 
-	TokenStream::from_iter(tokens.into_iter())
+	TokenStream::from_iter(tokens)
 }
 
 fn array_type(typ: &str, size: usize) -> TokenTree {
@@ -82,7 +82,7 @@ fn array_type(typ: &str, size: usize) -> TokenTree {
 		Literal::usize_unsuffixed(size).into(),
 	];
 	
-	TokenTree::from(Group::new(Delimiter::Bracket, TokenStream::from_iter(arr_type.into_iter())))
+	TokenTree::from(Group::new(Delimiter::Bracket, TokenStream::from_iter(arr_type)))
 }
 
 fn array_value (size: usize, to_token_tree: impl Fn(usize) -> TokenTree) -> TokenTree {
@@ -93,5 +93,5 @@ fn array_value (size: usize, to_token_tree: impl Fn(usize) -> TokenTree) -> Toke
 				TokenTree::from(Punct::new(',', Spacing::Alone)),
 			].into_iter())
 			.collect()
-	)).into()
+	))
 }